@@ -0,0 +1,135 @@
+use byteorder::LittleEndian;
+use core::fmt;
+use crate::error::BlockchainError;
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::util::*;
+
+pub const SATOSHIS_PER_BITCOIN: u64 = 100_000_000;
+
+// An amount of bitcoin in satoshis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const ONE_BTC: Amount = Amount(SATOSHIS_PER_BITCOIN);
+    // The most that can ever exist; no valid amount is larger.
+    pub const MAX_MONEY: Amount = Amount(21_000_000 * SATOSHIS_PER_BITCOIN);
+
+    pub fn from_sat(satoshis: u64) -> Amount {
+        Amount(satoshis)
+    }
+
+    // Converts from a BTC value, rounding to the nearest satoshi, as when
+    // reading amounts from Bitcoin Core's JSON.
+    pub fn from_btc(btc: f64) -> Result<Amount, BlockchainError> {
+        // f64::round needs std. Amounts are non-negative, so add a half and
+        // truncate instead.
+        let satoshis = btc * SATOSHIS_PER_BITCOIN as f64 + 0.5;
+        if !satoshis.is_finite() || satoshis < 0.0 ||
+           satoshis >= Amount::MAX_MONEY.0 as f64 + 1.0 {
+            return Err(BlockchainError::InvalidAmount);
+        }
+
+        Ok(Amount(satoshis as u64))
+    }
+
+    pub fn to_sat(&self) -> u64 {
+        self.0
+    }
+
+    pub fn to_btc(&self) -> f64 {
+        self.0 as f64 / SATOSHIS_PER_BITCOIN as f64
+    }
+
+    // Whether the amount is within the money range that consensus allows.
+    pub fn is_valid(&self) -> bool {
+        *self <= Amount::MAX_MONEY
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    // Sums amounts, failing if any amount or a partial sum leaves the money
+    // range. This is how consensus totals the outputs of a transaction.
+    pub fn checked_sum<I>(amounts: I) -> Result<Amount, BlockchainError>
+        where I: IntoIterator<Item = Amount>
+    {
+        let mut total = Amount::ZERO;
+        for amount in amounts {
+            if !amount.is_valid() {
+                return Err(BlockchainError::InvalidAmount);
+            }
+            total = total
+                .checked_add(amount)
+                .ok_or(BlockchainError::InvalidAmount)?;
+            if !total.is_valid() {
+                return Err(BlockchainError::InvalidAmount);
+            }
+        }
+
+        Ok(total)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{}.{:08} BTC",
+               self.0 / SATOSHIS_PER_BITCOIN,
+               self.0 % SATOSHIS_PER_BITCOIN)
+    }
+}
+
+impl Serializable for Amount {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        writer.write_u64::<LittleEndian>(self.0)?;
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        8
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Ok(Amount(reader.read_u64::<LittleEndian>()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_amount_conversions() {
+        assert_eq!(Amount::from_sat(112340000), Amount::from_btc(1.1234).unwrap());
+        assert_eq!(1.1234, Amount::from_sat(112340000).to_btc());
+        assert_eq!(Amount::MAX_MONEY, Amount::from_btc(21000000.0).unwrap());
+        assert!(Amount::from_btc(21000000.00000001).is_err());
+        assert!(Amount::from_btc(-0.1).is_err());
+        assert!(Amount::from_btc(f64::NAN).is_err());
+
+        assert_eq!("50.00000000 BTC", Amount::from_sat(5000000000).to_string());
+        assert_eq!("0.00000001 BTC", Amount::from_sat(1).to_string());
+    }
+
+    #[test]
+    fn test_checked_sum() {
+        let amounts = vec![Amount::ONE_BTC, Amount::from_sat(5)];
+        assert_eq!(Amount::from_sat(100000005),
+                   Amount::checked_sum(amounts).unwrap());
+
+        assert!(Amount::checked_sum(vec![Amount::MAX_MONEY, Amount::from_sat(1)]).is_err());
+        assert!(Amount::checked_sum(vec![Amount::from_sat(u64::MAX),
+                                         Amount::from_sat(1)])
+                        .is_err());
+        assert_eq!(None, Amount::ZERO.checked_sub(Amount::from_sat(1)));
+    }
+}
@@ -0,0 +1,279 @@
+// Async serialization of transactions and blocks over tokio's AsyncRead and
+// AsyncWrite, e.g. for streaming them over sockets.
+//
+// Deserializing walks the wire format with exact-size reads, so it never
+// reads past the end of the value, and then hands the bytes of each
+// transaction to the synchronous decoder. Blocks are read and written a
+// transaction at a time rather than buffered whole.
+
+use crate::block::{Block, BlockHeader};
+use crate::error::BlockchainError;
+use crate::transaction::{SEGWIT_FLAG, Transaction};
+use crate::util::*;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Reads from the underlying reader and keeps a copy of every byte read, up
+// to a limit on the total size.
+struct Recorder<'a, R> {
+    reader: &'a mut R,
+    buffer: Vec<u8>,
+    what: &'static str,
+    limit: usize,
+    total: u64,
+}
+
+impl<'a, R: AsyncRead + Unpin> Recorder<'a, R> {
+    fn new(reader: &'a mut R, what: &'static str, limit: usize) -> Recorder<'a, R> {
+        Recorder {
+            reader: reader,
+            buffer: Vec::new(),
+            what: what,
+            limit: limit,
+            total: 0,
+        }
+    }
+
+    async fn read(&mut self, size: usize) -> Result<&[u8], BlockchainError> {
+        self.total += size as u64;
+        check_limit(self.what, self.total, self.limit)?;
+        let start = self.buffer.len();
+        self.buffer.resize(start + size, 0);
+        self.reader.read_exact(&mut self.buffer[start..]).await?;
+
+        Ok(&self.buffer[start..])
+    }
+
+    async fn var_int(&mut self) -> Result<u64, BlockchainError> {
+        let start = self.buffer.len();
+        let size = match self.read(1).await?[0] {
+            0xfd => 2,
+            0xfe => 4,
+            0xff => 8,
+            _ => 0,
+        };
+        self.read(size).await?;
+
+        Ok(VarInt::deserialize(&self.buffer[start..])?.0)
+    }
+
+    async fn bytes(&mut self, what: &'static str, limit: usize) -> Result<(), BlockchainError> {
+        let length = self.var_int().await?;
+        check_limit(what, length, limit)?;
+        self.read(length as usize).await?;
+
+        Ok(())
+    }
+
+    // Records one transaction, following the layout that
+    // Transaction::deserialize_with_limits reads.
+    async fn transaction(&mut self, limits: &DeserializeLimits) -> Result<(), BlockchainError> {
+        self.read(4).await?;
+        let mut input_count = self.var_int().await?;
+        let witness = input_count == 0;
+        if witness {
+            if self.read(1).await?[0] != SEGWIT_FLAG {
+                return Err(BlockchainError::InvalidWitness("unknown segwit flag"));
+            }
+            input_count = self.var_int().await?;
+        }
+        for _ in 0..input_count {
+            self.read(36).await?;
+            self.bytes("script size", limits.max_script_size).await?;
+            self.read(4).await?;
+        }
+        for _ in 0..self.var_int().await? {
+            self.read(8).await?;
+            self.bytes("script size", limits.max_script_size).await?;
+        }
+        if witness {
+            for _ in 0..input_count {
+                for _ in 0..self.var_int().await? {
+                    self.bytes("witness item size", limits.max_script_size).await?;
+                }
+            }
+        }
+        self.read(4).await?;
+
+        Ok(())
+    }
+}
+
+impl Transaction {
+    pub async fn serialize_to_async<W>(&self, writer: &mut W) -> Result<(), BlockchainError>
+        where W: AsyncWrite + Unpin
+    {
+        writer.write_all(&self.serialize()?).await?;
+
+        Ok(())
+    }
+
+    pub async fn deserialize_from_async<R>(reader: &mut R) -> Result<Transaction, BlockchainError>
+        where R: AsyncRead + Unpin
+    {
+        Transaction::deserialize_with_limits_async(reader, &DeserializeLimits::default()).await
+    }
+
+    pub async fn deserialize_with_limits_async<R>(reader: &mut R,
+                                                  limits: &DeserializeLimits)
+                                                  -> Result<Transaction, BlockchainError>
+        where R: AsyncRead + Unpin
+    {
+        let mut recorder = Recorder::new(reader, "transaction size", limits.max_block_size);
+        recorder.transaction(limits).await?;
+        Transaction::deserialize_with_limits(&mut recorder.buffer.as_slice(), limits)
+    }
+}
+
+impl<T: Serializable + Clone> Block<T> {
+    pub async fn serialize_to_async<W>(&self, writer: &mut W) -> Result<(), BlockchainError>
+        where W: AsyncWrite + Unpin
+    {
+        writer.write_all(&self.header().serialize()?).await?;
+        writer
+            .write_all(&VarInt(self.data().len() as u64).serialize()?)
+            .await?;
+        for item in self.data() {
+            writer.write_all(&item.serialize()?).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Block<Transaction> {
+    pub async fn deserialize_from_async<R>(reader: &mut R)
+                                           -> Result<Block<Transaction>, BlockchainError>
+        where R: AsyncRead + Unpin
+    {
+        Block::deserialize_with_limits_async(reader, &DeserializeLimits::default()).await
+    }
+
+    // Like the synchronous version, checks the merkle root once the whole
+    // block has been read.
+    pub async fn deserialize_with_limits_async<R>(reader: &mut R,
+                                                  limits: &DeserializeLimits)
+                                                  -> Result<Block<Transaction>, BlockchainError>
+        where R: AsyncRead + Unpin
+    {
+        let mut recorder = Recorder::new(reader, "block size", limits.max_block_size);
+        recorder.read(80).await?;
+        let header = BlockHeader::deserialize(&recorder.buffer)?;
+        let count = recorder.var_int().await?;
+        check_limit("transaction count", count, limits.max_tx_count)?;
+
+        let mut transactions: Vec<Transaction> = Vec::new();
+        for _ in 0..count {
+            recorder.buffer.clear();
+            recorder.transaction(limits).await?;
+            transactions
+                .push(Transaction::deserialize_with_limits(&mut recorder.buffer.as_slice(),
+                                                           limits)?);
+        }
+
+        let block = Block::from_parts(header, transactions);
+        block.verify_merkle_root()?;
+
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::hash::Hash256;
+    use crate::locktime::{LockTime, Sequence};
+    use crate::script::Script;
+    use crate::transaction::{Input, Outpoint, Output, Witness};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    // Hands out one byte per read, and is only ready every other poll.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(mut self: Pin<&mut Self>,
+                     context: &mut Context<'_>,
+                     buf: &mut ReadBuf<'_>)
+                     -> Poll<io::Result<()>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                context.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            if let Some((byte, rest)) = self.data.split_first() {
+                buf.put_slice(&[*byte]);
+                self.data = rest;
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn test_block() -> Block<Transaction> {
+        let coinbase = Transaction::new(1,
+                                        &[Input::new(Outpoint::null(),
+                                                     Script::from(vec![0x51]),
+                                                     Sequence::MAX)],
+                                        &[Output::new(Amount::from_sat(5000000000),
+                                                      Script::from(vec![0x52]))],
+                                        LockTime::ZERO);
+        let input = Input::new(Outpoint::new(Hash256::from([1; 32]), 0),
+                               Script::new(),
+                               Sequence::MAX)
+                .with_witness(Witness::new(vec![vec![0x30; 72], vec![0x02; 33]]));
+        let spend = Transaction::new(2,
+                                     &[input],
+                                     &[Output::new(Amount::from_sat(1000), Script::new())],
+                                     LockTime::ZERO);
+        Block::new(1, Hash256::default(), &[coinbase, spend], 0x207fffff).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let block = test_block();
+        let mut serialized: Vec<u8> = Vec::new();
+        block.serialize_to_async(&mut serialized).await.unwrap();
+        assert_eq!(block.serialize().unwrap(), serialized);
+
+        // Trailing bytes belong to whatever comes next and are left unread.
+        serialized.push(0xaa);
+        let mut reader = Trickle {
+            data: &serialized,
+            ready: false,
+        };
+        assert_eq!(block, Block::deserialize_from_async(&mut reader).await.unwrap());
+        assert_eq!(&[0xaa], reader.data);
+
+        let transaction = &block.data()[1];
+        let mut serialized: Vec<u8> = Vec::new();
+        transaction.serialize_to_async(&mut serialized).await.unwrap();
+        let mut reader = Trickle {
+            data: &serialized,
+            ready: false,
+        };
+        assert_eq!(*transaction,
+                   Transaction::deserialize_from_async(&mut reader).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let serialized = test_block().serialize().unwrap();
+        let mut truncated = &serialized[..serialized.len() - 1];
+        assert!(matches!(Block::deserialize_from_async(&mut truncated).await,
+                         Err(BlockchainError::TruncatedInput)));
+
+        let limits = DeserializeLimits {
+            max_block_size: serialized.len() - 1,
+            ..DeserializeLimits::default()
+        };
+        assert!(matches!(Block::deserialize_with_limits_async(&mut serialized.as_slice(),
+                                                              &limits)
+                                 .await,
+                         Err(BlockchainError::LimitExceeded { what: "block size", .. })));
+    }
+}
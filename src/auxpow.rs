@@ -0,0 +1,416 @@
+use byteorder::LittleEndian;
+use crate::block::{BlockHeader, check_proof_of_work};
+use crate::error::BlockchainError;
+use crate::hash::Hash256;
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::params::ChainParams;
+use crate::prelude::*;
+use crate::transaction::Transaction;
+use crate::util::*;
+
+// Header version bit marking a block that carries an aux proof-of-work.
+pub const VERSION_AUXPOW: u32 = 1 << 8;
+
+// Marker preceding the chain merkle root in the parent coinbase script.
+const MERGED_MINING_HEADER: [u8; 4] = [0xfa, 0xbe, b'm', b'm'];
+
+const MAX_CHAIN_MERKLE_DEPTH: usize = 30;
+
+pub fn chain_id(version: u32) -> u32 {
+    version >> 16
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleBranch {
+    hashes: Vec<Hash256>,
+    index: u32,
+}
+
+impl MerkleBranch {
+    pub fn new(hashes: Vec<Hash256>, index: u32) -> MerkleBranch {
+        MerkleBranch {
+            hashes: hashes,
+            index: index,
+        }
+    }
+
+    pub fn root(&self, leaf: &Hash256) -> Hash256 {
+        merkle_branch_root(leaf, &self.hashes, self.index)
+    }
+}
+
+impl Serializable for MerkleBranch {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        VarInt(self.hashes.len() as u64).serialize_to(writer)?;
+        for hash in &self.hashes {
+            hash.serialize_to(writer)?;
+        }
+        writer.write_u32::<LittleEndian>(self.index)?;
+
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        let count = VarInt::deserialize_from(reader)?;
+        let mut hashes: Vec<Hash256> = Vec::new();
+        for _ in 0..count.0 {
+            hashes.push(Hash256::deserialize_from(reader)?);
+        }
+        let index = reader.read_u32::<LittleEndian>()?;
+
+        Ok(MerkleBranch {
+               hashes: hashes,
+               index: index,
+           })
+    }
+
+    fn serialized_size(&self) -> usize {
+        VarInt(self.hashes.len() as u64).serialized_size() + 32 * self.hashes.len() + 4
+    }
+}
+
+// Proof that a block of a merge-mined chain was committed to by a block of a
+// parent chain: the parent's coinbase commits to a merkle root of aux chain
+// block hashes, and the parent header commits to the coinbase.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuxPow {
+    coinbase_tx: Transaction,
+    parent_block_hash: Hash256,
+    coinbase_branch: MerkleBranch,
+    chain_branch: MerkleBranch,
+    parent_header: BlockHeader,
+}
+
+impl AuxPow {
+    pub fn new(coinbase_tx: Transaction,
+               coinbase_branch: MerkleBranch,
+               chain_branch: MerkleBranch,
+               parent_header: BlockHeader)
+               -> Result<AuxPow, BlockchainError> {
+        Ok(AuxPow {
+               coinbase_tx: coinbase_tx,
+               parent_block_hash: parent_header.hash()?,
+               coinbase_branch: coinbase_branch,
+               chain_branch: chain_branch,
+               parent_header: parent_header,
+           })
+    }
+
+    pub fn parent_header(&self) -> &BlockHeader {
+        &self.parent_header
+    }
+
+    // Checks that this proof commits to the aux block `aux_hash` for the
+    // given chain. Proof-of-work on the parent header is checked separately.
+    pub fn check(&self, aux_hash: &Hash256, our_chain_id: u32) -> Result<(), BlockchainError> {
+        if self.coinbase_branch.index != 0 {
+            return Err(BlockchainError::InvalidAuxPow("coinbase is not the first transaction"));
+        }
+        if chain_id(self.parent_header.version()) == our_chain_id {
+            return Err(BlockchainError::InvalidAuxPow("parent block has our chain ID"));
+        }
+        if self.chain_branch.hashes.len() > MAX_CHAIN_MERKLE_DEPTH {
+            return Err(BlockchainError::InvalidAuxPow("chain merkle branch too long"));
+        }
+
+        let coinbase_hash = self.coinbase_tx.txid()?;
+        if self.coinbase_branch.root(&coinbase_hash) != *self.parent_header.merkle_root_hash() {
+            return Err(BlockchainError::InvalidAuxPow("coinbase is not in the parent block"));
+        }
+
+        let mut root = self.chain_branch.root(aux_hash).to_bytes();
+        root.reverse();
+        let script = match self.coinbase_tx.inputs().first() {
+            Some(input) => input.script().as_bytes(),
+            None => return Err(BlockchainError::InvalidAuxPow("coinbase has no inputs")),
+        };
+
+        let root_position = match find(script, &root) {
+            Some(position) => position,
+            None => {
+                return Err(BlockchainError::InvalidAuxPow("chain merkle root not in \
+                                                           coinbase"));
+            }
+        };
+        match find(script, &MERGED_MINING_HEADER) {
+            Some(header_position) => {
+                if find(&script[header_position + 1..], &MERGED_MINING_HEADER).is_some() {
+                    return Err(BlockchainError::InvalidAuxPow("multiple merged mining headers"));
+                }
+                if header_position + MERGED_MINING_HEADER.len() != root_position {
+                    return Err(BlockchainError::InvalidAuxPow("merged mining header is not \
+                                                               just before the chain root"));
+                }
+            }
+            None => {
+                // Legacy commitments without a header must start early in the
+                // script.
+                if root_position > 20 {
+                    return Err(BlockchainError::InvalidAuxPow("chain merkle root starts too \
+                                                               late in coinbase"));
+                }
+            }
+        }
+
+        let mut rest = &script[root_position + root.len()..];
+        if rest.len() < 8 {
+            return Err(BlockchainError::InvalidAuxPow("missing tree size and nonce"));
+        }
+        let size = rest.read_u32::<LittleEndian>()?;
+        let nonce = rest.read_u32::<LittleEndian>()?;
+        let depth = self.chain_branch.hashes.len();
+        if size != 1 << depth {
+            return Err(BlockchainError::InvalidAuxPow("tree size does not match branch length"));
+        }
+        if self.chain_branch.index != expected_index(nonce, our_chain_id, depth) {
+            return Err(BlockchainError::InvalidAuxPow("wrong index in chain merkle tree"));
+        }
+
+        Ok(())
+    }
+}
+
+// The slot in the chain merkle tree that a chain must use, derived from the
+// nonce so that different chains can't claim the same slot.
+fn expected_index(nonce: u32, chain_id: u32, depth: usize) -> u32 {
+    let mut rand = nonce;
+    rand = rand.wrapping_mul(1103515245).wrapping_add(12345);
+    rand = rand.wrapping_add(chain_id);
+    rand = rand.wrapping_mul(1103515245).wrapping_add(12345);
+    rand % (1 << depth)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+impl Serializable for AuxPow {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.coinbase_tx.serialize_to(writer)?;
+        self.parent_block_hash.serialize_to(writer)?;
+        self.coinbase_branch.serialize_to(writer)?;
+        self.chain_branch.serialize_to(writer)?;
+        self.parent_header.serialize_to(writer)?;
+
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        AuxPow::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        let coinbase_tx = Transaction::deserialize_with_limits(reader, limits)?;
+        let parent_block_hash = Hash256::deserialize_from(reader)?;
+        let coinbase_branch = MerkleBranch::deserialize_from(reader)?;
+        let chain_branch = MerkleBranch::deserialize_from(reader)?;
+        let parent_header = BlockHeader::deserialize_from(reader)?;
+
+        Ok(AuxPow {
+               coinbase_tx: coinbase_tx,
+               parent_block_hash: parent_block_hash,
+               coinbase_branch: coinbase_branch,
+               chain_branch: chain_branch,
+               parent_header: parent_header,
+           })
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.coinbase_tx.serialized_size() + self.parent_block_hash.serialized_size() +
+        self.coinbase_branch.serialized_size() + self.chain_branch.serialized_size() +
+        self.parent_header.serialized_size()
+    }
+}
+
+// A block header extended with an optional aux proof-of-work, present when
+// the header's version has the VERSION_AUXPOW bit set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuxPowHeader {
+    header: BlockHeader,
+    aux_pow: Option<AuxPow>,
+}
+
+impl AuxPowHeader {
+    pub fn new(header: BlockHeader, aux_pow: Option<AuxPow>) -> AuxPowHeader {
+        AuxPowHeader {
+            header: header,
+            aux_pow: aux_pow,
+        }
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn aux_pow(&self) -> Option<&AuxPow> {
+        self.aux_pow.as_ref()
+    }
+
+    pub fn validate_pow(&self, params: &ChainParams) -> Result<(), BlockchainError> {
+        let version = self.header.version();
+        if let Some(expected) = params.aux_pow_chain_id {
+            if chain_id(version) != expected {
+                return Err(BlockchainError::InvalidAuxPow("wrong chain ID"));
+            }
+        }
+
+        match (version & VERSION_AUXPOW != 0, self.aux_pow.as_ref(), params.aux_pow_chain_id) {
+            (false, None, _) => self.header.validate_pow(params),
+            (true, Some(aux_pow), Some(id)) => {
+                aux_pow.check(&self.header.hash()?, id)?;
+                check_proof_of_work(&aux_pow.parent_header.hash()?, self.header.bits(), params)
+            }
+            (true, Some(_), None) => {
+                Err(BlockchainError::InvalidAuxPow("chain does not accept aux proof-of-work"))
+            }
+            _ => Err(BlockchainError::InvalidAuxPow("version flag does not match aux data")),
+        }
+    }
+}
+
+impl Serializable for AuxPowHeader {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.header.serialize_to(writer)?;
+        if let Some(ref aux_pow) = self.aux_pow {
+            aux_pow.serialize_to(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        AuxPowHeader::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        let header = BlockHeader::deserialize_from(reader)?;
+        let aux_pow = if header.version() & VERSION_AUXPOW != 0 {
+            Some(AuxPow::deserialize_with_limits(reader, limits)?)
+        } else {
+            None
+        };
+
+        Ok(AuxPowHeader {
+               header: header,
+               aux_pow: aux_pow,
+           })
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.header.serialized_size() + self.aux_pow.as_ref().map_or(0, |a| a.serialized_size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hash::double_hash;
+    use crate::amount::Amount;
+    use crate::locktime::{LockTime, Sequence};
+    use crate::script::Script;
+    use crate::transaction::{Input, Outpoint, Output};
+
+    const CHAIN_ID: u32 = 1;
+
+    fn params() -> ChainParams {
+        ChainParams { aux_pow_chain_id: Some(CHAIN_ID), ..ChainParams::regtest() }
+    }
+
+    fn aux_header(nonce: u32) -> BlockHeader {
+        BlockHeader::new((CHAIN_ID << 16) | VERSION_AUXPOW | 4,
+                         Hash256::default(),
+                         Hash256::default(),
+                         1500000000,
+                         0x207fffff,
+                         nonce)
+    }
+
+    fn merge_mine(aux_hash: &Hash256) -> AuxPow {
+        let mut root = aux_hash.to_bytes();
+        root.reverse();
+        let mut script = vec![0x03, 0x01, 0x02, 0x03];
+        script.extend_from_slice(&MERGED_MINING_HEADER);
+        script.extend_from_slice(&root);
+        script.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let coinbase = Transaction::new(1,
+                                        &[Input::new(Outpoint::null(),
+                                                     Script::from(script),
+                                                     Sequence::MAX)],
+                                        &[Output::new(Amount::from_sat(5000000000),
+                                                      Script::new())],
+                                        LockTime::ZERO);
+        let merkle_root = coinbase.txid().unwrap();
+
+        let mut nonce = 0;
+        let parent_header = loop {
+            let header = BlockHeader::new(0x20000000,
+                                          Hash256::default(),
+                                          merkle_root,
+                                          1500000000,
+                                          0x207fffff,
+                                          nonce);
+            if header.validate_pow(&ChainParams::regtest()).is_ok() {
+                break header;
+            }
+            nonce += 1;
+        };
+
+        AuxPow::new(coinbase,
+                    MerkleBranch::new(Vec::new(), 0),
+                    MerkleBranch::new(Vec::new(), 0),
+                    parent_header)
+                .unwrap()
+    }
+
+    #[test]
+    fn test_valid_aux_pow() {
+        let header = aux_header(0);
+        let aux_pow = merge_mine(&header.hash().unwrap());
+        let aux_header = AuxPowHeader::new(header, Some(aux_pow));
+        aux_header.validate_pow(&params()).unwrap();
+
+        let serialized = aux_header.serialize().unwrap();
+        assert_eq!(serialized.len(), aux_header.serialized_size());
+        assert_eq!(aux_header, AuxPowHeader::deserialize(&serialized).unwrap());
+    }
+
+    #[test]
+    fn test_aux_pow_for_other_block() {
+        let aux_pow = merge_mine(&aux_header(0).hash().unwrap());
+        let aux_header = AuxPowHeader::new(aux_header(1), Some(aux_pow));
+        assert!(matches!(aux_header.validate_pow(&params()),
+                         Err(BlockchainError::InvalidAuxPow(_))));
+    }
+
+    #[test]
+    fn test_aux_pow_flag_mismatch() {
+        let aux_header = AuxPowHeader::new(aux_header(0), None);
+        assert!(matches!(aux_header.validate_pow(&params()),
+                         Err(BlockchainError::InvalidAuxPow(_))));
+    }
+
+    #[test]
+    fn test_expected_index() {
+        assert_eq!(0, expected_index(12345, CHAIN_ID, 0));
+        assert!(expected_index(12345, CHAIN_ID, 4) < 16);
+    }
+
+    #[test]
+    fn test_merkle_branch_root() {
+        let leaves: Vec<Hash256> = (0..4u8).map(|i| double_hash(&[i])).collect();
+        let data: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i]).collect();
+        let left = merkle_branch_root(&leaves[0], &leaves[1..2], 0);
+        let right = merkle_branch_root(&leaves[2], &leaves[3..4], 0);
+        let branch = MerkleBranch::new(vec![leaves[3], left], 2);
+        assert_eq!(calculate_merkle(&data), branch.root(&leaves[2]));
+        assert_eq!(calculate_merkle(&data),
+                   merkle_branch_root(&left, &[right], 0));
+    }
+}
@@ -1,45 +1,237 @@
-
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{self, Read, Write};
+use byteorder::LittleEndian;
+use core::fmt;
+use crate::error::BlockchainError;
+use crate::hash::{Hash256, double_hash};
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::params::ChainParams;
+use crate::prelude::*;
+use crate::uint::U256;
+use crate::util::*;
+#[cfg(feature = "std")]
 use time;
-use util::*;
 
-const BLOCK_MAGIC_NUMBER: u32 = 0xD9B4BEF9;
+// Expands the compact `bits` encoding of a target: a one-byte exponent and
+// a three-byte mantissa with a sign bit, as in Bitcoin Core's SetCompact.
+// Negative, zero and overflowing targets are rejected.
+pub fn compact_to_target(bits: u32) -> Result<U256, BlockchainError> {
+    let exponent = bits >> 24;
+    let mantissa = bits & 0x007fffff;
+    let negative = mantissa != 0 && bits & 0x00800000 != 0;
+    let overflow = mantissa != 0 &&
+                   (exponent > 34 || (mantissa > 0xff && exponent > 33) ||
+                    (mantissa > 0xffff && exponent > 32));
+    if negative || overflow {
+        return Err(BlockchainError::InvalidTarget);
+    }
+
+    let target = if exponent <= 3 {
+        U256::from(mantissa as u64 >> (8 * (3 - exponent)))
+    } else {
+        U256::from(mantissa as u64) << (8 * (exponent - 3))
+    };
+    if target.is_zero() {
+        return Err(BlockchainError::InvalidTarget);
+    }
 
+    Ok(target)
+}
+
+// 2^256 doesn't fit in a U256, so this uses the equivalent
+// (2^256 - target - 1) / (target + 1) + 1.
+fn target_work(target: U256) -> Result<U256, BlockchainError> {
+    let divisor = target
+        .checked_add(U256::ONE)
+        .ok_or(BlockchainError::InvalidTarget)?;
+    Ok(!target / divisor + U256::ONE)
+}
+
+// The compact encoding of a target, as in Bitcoin Core's GetCompact. Bits
+// beyond the three-byte mantissa are dropped.
+pub fn target_to_compact(target: U256) -> u32 {
+    let mut size = target.bits().div_ceil(8);
+    let mut mantissa = if size <= 3 {
+        (target.low_u64() << (8 * (3 - size))) as u32
+    } else {
+        (target >> (8 * (size - 3))).low_u64() as u32
+    };
+    // The top mantissa bit is the sign, so move a set bit into the exponent.
+    if mantissa & 0x00800000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    mantissa | (size << 24)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct BlockHeader {
     version: u32,
-    previous_hash: Vec<u8>,
-    merkle_root_hash: Vec<u8>,
+    previous_hash: Hash256,
+    merkle_root_hash: Hash256,
     timestamp: u32,
     bits: u32,
     nonce: u32,
 }
 
 impl BlockHeader {
-    pub fn hash(&self) -> Result<Vec<u8>, io::Error> {
-        Ok(double_hash(self.serialize()?.as_slice())?)
+    pub fn new(version: u32,
+               previous_hash: Hash256,
+               merkle_root_hash: Hash256,
+               timestamp: u32,
+               bits: u32,
+               nonce: u32)
+               -> BlockHeader {
+        BlockHeader {
+            version: version,
+            previous_hash: previous_hash,
+            merkle_root_hash: merkle_root_hash,
+            timestamp: timestamp,
+            bits: bits,
+            nonce: nonce,
+        }
+    }
+
+    pub fn hash(&self) -> Result<Hash256, BlockchainError> {
+        Ok(double_hash(self.serialize()?.as_slice()))
+    }
+
+    pub fn target(&self) -> Result<U256, BlockchainError> {
+        compact_to_target(self.bits)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn previous_hash(&self) -> &Hash256 {
+        &self.previous_hash
+    }
+
+    pub fn merkle_root_hash(&self) -> &Hash256 {
+        &self.merkle_root_hash
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn nonce(&self) -> u32 {
+        self.nonce
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u32) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn set_nonce(&mut self, nonce: u32) {
+        self.nonce = nonce;
+    }
+
+    // The expected number of hashes needed to find a header at this target,
+    // 2^256 / (target + 1).
+    pub fn work(&self) -> Result<U256, BlockchainError> {
+        target_work(compact_to_target(self.bits)?)
+    }
+
+    // Checks that the header hash meets its own target, whatever that target
+    // is. It doesn't enforce a chain's proof-of-work limit; validate_pow
+    // does.
+    pub fn check_own_target(&self) -> Result<(), BlockchainError> {
+        if U256::from_le_bytes(self.hash()?.to_bytes()) > compact_to_target(self.bits)? {
+            return Err(BlockchainError::HashAboveTarget);
+        }
+
+        Ok(())
+    }
+
+    // Checks the header hash against its target and the target against the
+    // chain's proof-of-work limit.
+    pub fn validate_pow(&self, params: &ChainParams) -> Result<(), BlockchainError> {
+        check_proof_of_work(&self.hash()?, self.bits, params)
+    }
+}
+
+// The total work of a chain of headers. Comparing two chains' work is how
+// the best chain is chosen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChainWork(U256);
+
+impl ChainWork {
+    pub fn new() -> ChainWork {
+        ChainWork(U256::ZERO)
+    }
+
+    pub fn from_headers(headers: &[BlockHeader]) -> Result<ChainWork, BlockchainError> {
+        let mut chain_work = ChainWork::new();
+        for header in headers {
+            chain_work.add_header(header)?;
+        }
+
+        Ok(chain_work)
+    }
+
+    pub fn add_header(&mut self, header: &BlockHeader) -> Result<(), BlockchainError> {
+        self.0 = self.0
+            .checked_add(header.work()?)
+            .ok_or(BlockchainError::InvalidTarget)?;
+
+        Ok(())
+    }
+
+    pub fn to_u256(&self) -> U256 {
+        self.0
     }
 }
 
+impl fmt::Display for ChainWork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+
+// Checks that `hash` meets the target encoded in `bits` and that the target
+// is within the chain's proof-of-work limit. BlockHeader::check_own_target
+// only does the first.
+pub fn check_proof_of_work(hash: &Hash256,
+                           bits: u32,
+                           params: &ChainParams)
+                           -> Result<(), BlockchainError> {
+    let target = compact_to_target(bits)?;
+    if target > params.pow_limit {
+        return Err(BlockchainError::TargetTooEasy);
+    }
+
+    if U256::from_le_bytes(hash.to_bytes()) > target {
+        return Err(BlockchainError::HashAboveTarget);
+    }
+
+    Ok(())
+}
+
 impl Serializable for BlockHeader {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.write_u32::<LittleEndian>(self.version)?;
-        buffer.write_all(self.previous_hash.as_slice())?;
-        buffer.write_all(self.merkle_root_hash.as_slice())?;
-        buffer.write_u32::<LittleEndian>(self.timestamp)?;
-        buffer.write_u32::<LittleEndian>(self.bits)?;
-        buffer.write_u32::<LittleEndian>(self.nonce)?;
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        writer.write_u32::<LittleEndian>(self.version)?;
+        self.previous_hash.serialize_to(writer)?;
+        self.merkle_root_hash.serialize_to(writer)?;
+        writer.write_u32::<LittleEndian>(self.timestamp)?;
+        writer.write_u32::<LittleEndian>(self.bits)?;
+        writer.write_u32::<LittleEndian>(self.nonce)?;
 
-        Ok(buffer)
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        80
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<BlockHeader, io::Error> {
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<BlockHeader, BlockchainError> {
         let version = reader.read_u32::<LittleEndian>()?;
-        let mut previous_hash = vec![0; 32];
-        reader.read_exact(previous_hash.as_mut_slice())?;
-        let mut merkle_root_hash = vec![0; 32];
-        reader.read_exact(merkle_root_hash.as_mut_slice())?;
+        let previous_hash = Hash256::deserialize_from(reader)?;
+        let merkle_root_hash = Hash256::deserialize_from(reader)?;
         let timestamp = reader.read_u32::<LittleEndian>()?;
         let bits = reader.read_u32::<LittleEndian>()?;
         let nonce = reader.read_u32::<LittleEndian>()?;
@@ -55,6 +247,8 @@ impl Serializable for BlockHeader {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Block<T: Serializable + Clone> {
     header: BlockHeader,
     data: Vec<T>,
@@ -62,24 +256,18 @@ pub struct Block<T: Serializable + Clone> {
 
 impl<T: Serializable + Clone> Block<T> {
     pub fn new(version: u32,
-               previous_hash: Vec<u8>,
+               previous_hash: Hash256,
                values: &[T],
                bits: u32)
-               -> Result<Block<T>, io::Error> {
-        let now = time::now().to_timespec().sec as u32;
-
-        let mut data: Vec<Vec<u8>> = Vec::new();
-        for value in values {
-            data.push(value.serialize()?);
-        }
-        let merkle = calculate_merkle(&data)?;
+               -> Result<Block<T>, BlockchainError> {
+        let merkle = Block::merkle_root(values)?;
 
         Ok(Block {
                header: BlockHeader {
                    version: version,
                    previous_hash: previous_hash,
                    merkle_root_hash: merkle,
-                   timestamp: now,
+                   timestamp: now(),
                    bits: bits,
                    nonce: 0,
                },
@@ -87,56 +275,497 @@ impl<T: Serializable + Clone> Block<T> {
            })
     }
 
+    // Assembles a block from an existing header, e.g. one that was parsed
+    // rather than built. The merkle root is not checked.
+    pub fn from_parts(header: BlockHeader, data: Vec<T>) -> Block<T> {
+        Block {
+            header: header,
+            data: data,
+        }
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    // Replaces the block's contents and updates the merkle root to match.
+    pub fn set_data(&mut self, values: &[T]) -> Result<(), BlockchainError> {
+        self.header.merkle_root_hash = Block::merkle_root(values)?;
+        self.data = values.to_vec();
+
+        Ok(())
+    }
+
+    // Appends an item and updates the merkle root to match.
+    pub fn push_item(&mut self, item: T) -> Result<(), BlockchainError> {
+        self.data.push(item);
+        self.header.merkle_root_hash = Block::merkle_root(&self.data)?;
+
+        Ok(())
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u32) {
+        self.header.set_timestamp(timestamp);
+    }
+
     pub fn set_nonce(&mut self, nonce: u32) {
-        self.header.nonce = nonce;
+        self.header.set_nonce(nonce);
+    }
+
+    // Checks that the header's merkle root matches the block's contents.
+    pub fn verify_merkle_root(&self) -> Result<(), BlockchainError> {
+        if Block::merkle_root(&self.data)? != self.header.merkle_root_hash {
+            return Err(BlockchainError::InvalidMerkleRoot);
+        }
+
+        Ok(())
+    }
+
+    fn merkle_root(values: &[T]) -> Result<Hash256, BlockchainError> {
+        let mut stream = MerkleStream::new();
+        for value in values {
+            stream.push_hash(value.merkle_leaf()?);
+        }
+
+        Ok(stream.finish())
     }
 
-    pub fn header_hash(&self) -> Result<Vec<u8>, io::Error> {
+    pub fn header_hash(&self) -> Result<Hash256, BlockchainError> {
         self.header.hash()
     }
 }
 
-impl<T: Serializable + Clone> Serializable for Block<T> {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.write_u32::<LittleEndian>(BLOCK_MAGIC_NUMBER)?;
-        buffer.write_u32::<LittleEndian>(0)?;
-        buffer.write_all(self.header.serialize()?.as_ref())?;
-        buffer
-            .write_all(VarInt(self.data.len() as u64).serialize()?.as_slice())?;
-        for item in &self.data {
-            buffer.write_all(item.serialize()?.as_ref())?;
-        }
+// Assembles a block item by item. The merkle root is computed when the
+// block is built, and the timestamp defaults to the current time (or zero
+// without std, where there is no clock).
+#[derive(Clone, Debug)]
+pub struct BlockBuilder<T: Serializable + Clone> {
+    version: u32,
+    previous_hash: Hash256,
+    bits: u32,
+    timestamp: Option<u32>,
+    nonce: u32,
+    data: Vec<T>,
+}
 
-        let size: u32 = buffer.len() as u32 - 8;
-        {
-            let mut slice = &mut buffer[4..8];
-            slice.write_u32::<LittleEndian>(size)?;
+impl<T: Serializable + Clone> BlockBuilder<T> {
+    pub fn new() -> BlockBuilder<T> {
+        BlockBuilder {
+            version: 1,
+            previous_hash: Hash256::default(),
+            bits: 0x1d00ffff,
+            timestamp: None,
+            nonce: 0,
+            data: Vec::new(),
         }
+    }
+
+    pub fn version(mut self, version: u32) -> BlockBuilder<T> {
+        self.version = version;
+        self
+    }
+
+    pub fn previous_hash(mut self, previous_hash: Hash256) -> BlockBuilder<T> {
+        self.previous_hash = previous_hash;
+        self
+    }
+
+    pub fn bits(mut self, bits: u32) -> BlockBuilder<T> {
+        self.bits = bits;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u32) -> BlockBuilder<T> {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u32) -> BlockBuilder<T> {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn push(mut self, item: T) -> BlockBuilder<T> {
+        self.data.push(item);
+        self
+    }
+
+    pub fn build(self) -> Result<Block<T>, BlockchainError> {
+        let timestamp = self.timestamp.unwrap_or_else(now);
+        let merkle = Block::merkle_root(&self.data)?;
+
+        Ok(Block {
+               header: BlockHeader {
+                   version: self.version,
+                   previous_hash: self.previous_hash,
+                   merkle_root_hash: merkle,
+                   timestamp: timestamp,
+                   bits: self.bits,
+                   nonce: self.nonce,
+               },
+               data: self.data,
+           })
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn now() -> u32 {
+    time::now().to_timespec().sec as u32
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn now() -> u32 {
+    0
+}
+
+impl<T: Serializable + Clone> Default for BlockBuilder<T> {
+    fn default() -> BlockBuilder<T> {
+        BlockBuilder::new()
+    }
+}
+
+impl<T: Serializable + Clone> Block<T> {
+    // Block file framing: the network magic and the size of the serialized
+    // block, followed by the block itself.
+    pub fn serialize_framed(&self, params: &ChainParams) -> Result<Vec<u8>, BlockchainError> {
+        let block = self.serialize()?;
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.write_u32::<LittleEndian>(params.magic)?;
+        buffer.write_u32::<LittleEndian>(block.len() as u32)?;
+        buffer.write_all(block.as_slice())?;
 
         Ok(buffer)
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Block<T>, io::Error> {
+    pub fn deserialize_framed<R: Read>(reader: &mut R,
+                                       params: &ChainParams)
+                                       -> Result<Block<T>, BlockchainError> {
         let magic = reader.read_u32::<LittleEndian>()?;
-        if magic != BLOCK_MAGIC_NUMBER {
-            // TODO: Replace with actual error
-            panic!("Bad block header found: {:?}", magic);
+        if magic != params.magic {
+            return Err(BlockchainError::BadMagic {
+                           expected: params.magic,
+                           found: magic,
+                       });
         }
         let size = reader.read_u32::<LittleEndian>()?;
+        check_limit("block size", size as u64, DeserializeLimits::default().max_block_size)?;
         let mut buffer = vec![0; size as usize];
         reader.read_exact(buffer.as_mut_slice())?;
 
-        let header = BlockHeader::deserialize(&mut buffer.as_slice())?;
-        let data_size = VarInt::deserialize(&mut buffer.as_slice())?;
+        // The block must fill its frame exactly, so that the next frame in
+        // the stream starts where this one ends.
+        Block::deserialize(&buffer)
+    }
+}
+
+impl<T: Serializable + Clone> Block<T> {
+    // Deserializes without checking the merkle root, e.g. to inspect a block
+    // that is already known to be corrupt.
+    pub fn deserialize_unchecked<R: Read>(reader: &mut R,
+                                          limits: &DeserializeLimits)
+                                          -> Result<Block<T>, BlockchainError> {
+        // Allow one byte past the limit, so that a block which reads it is
+        // known to be too big rather than just truncated.
+        let allowance = limits.max_block_size as u64 + 1;
+        let mut limited = reader.take(allowance);
+        let result = Block::read_limited(&mut limited, limits);
+        let read = allowance - limited.limit();
+        check_limit("block size", read, limits.max_block_size)?;
+        result
+    }
+
+    fn read_limited<R: Read>(reader: &mut R,
+                             limits: &DeserializeLimits)
+                             -> Result<Block<T>, BlockchainError> {
+        let header = BlockHeader::deserialize_from(reader)?;
+        let data_size = VarInt::deserialize_from(reader)?;
+        check_limit("transaction count", data_size.0, limits.max_tx_count)?;
         let mut data: Vec<T> = Vec::new();
         for _ in 0..data_size.0 {
-            data.push(T::deserialize(&mut buffer.as_slice())?);
+            data.push(T::deserialize_with_limits(reader, limits)?);
         }
 
         Ok(Block {
                header: header,
-               data: Vec::new(),
+               data: data,
            })
     }
 }
+
+impl<T: Serializable + Clone> Serializable for Block<T> {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.header.serialize_to(writer)?;
+        VarInt(self.data.len() as u64).serialize_to(writer)?;
+        for item in &self.data {
+            item.serialize_to(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.header.serialized_size() + VarInt(self.data.len() as u64).serialized_size() +
+        self.data.iter().map(|item| item.serialized_size()).sum::<usize>()
+    }
+
+    fn round_trips(&self) -> bool {
+        self.data.iter().all(|item| item.round_trips())
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Block<T>, BlockchainError> {
+        Block::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Block<T>, BlockchainError> {
+        let block = Block::deserialize_unchecked(reader, limits)?;
+        block.verify_merkle_root()?;
+
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::locktime::{LockTime, Sequence};
+    use crate::params::ChainParams;
+    use crate::script::Script;
+    use crate::transaction::{Input, Outpoint, Output, Transaction};
+
+    fn genesis_header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            previous_hash: Hash256::default(),
+            merkle_root_hash: "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+                .parse()
+                .unwrap(),
+            timestamp: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        }
+    }
+
+    #[test]
+    fn test_compact_target() {
+        let mut expected = [0; 32];
+        expected[4] = 0xff;
+        expected[5] = 0xff;
+        assert_eq!(U256::from_be_bytes(expected), compact_to_target(0x1d00ffff).unwrap());
+        assert_eq!(U256::from(0x12), compact_to_target(0x01123456).unwrap());
+        assert_eq!(U256::from(0x1234), compact_to_target(0x02123456).unwrap());
+        assert_eq!(U256::from(0x123456) << 8, compact_to_target(0x04123456).unwrap());
+
+        assert!(matches!(compact_to_target(0x04923456), Err(BlockchainError::InvalidTarget)));
+        assert!(matches!(compact_to_target(0xff123456), Err(BlockchainError::InvalidTarget)));
+        assert!(matches!(compact_to_target(0x00000000), Err(BlockchainError::InvalidTarget)));
+        assert!(matches!(compact_to_target(0x01003456), Err(BlockchainError::InvalidTarget)));
+
+        for bits in &[0x1d00ffff, 0x207fffff, 0x1b0404cb, 0x02123400, 0x05009234] {
+            assert_eq!(*bits, target_to_compact(compact_to_target(*bits).unwrap()));
+        }
+        // A mantissa with the sign bit set moves up a byte.
+        assert_eq!(0x02008000, target_to_compact(U256::from(0x80)));
+    }
+
+    #[test]
+    fn test_work() {
+        let genesis = genesis_header();
+        assert_eq!(U256::from(0x100010001), genesis.work().unwrap());
+
+        // The easiest possible regtest target takes two hashes on average.
+        let easy = BlockHeader::new(1, Hash256::default(), Hash256::default(), 0, 0x207fffff, 0);
+        assert_eq!(U256::from(2), easy.work().unwrap());
+
+        let chain_work = ChainWork::from_headers(&[genesis.clone(), genesis.clone()]).unwrap();
+        assert_eq!(U256::from(0x200020002), chain_work.to_u256());
+        assert!(chain_work > ChainWork::from_headers(&[genesis]).unwrap());
+        assert_eq!("0000000000000000000000000000000000000000000000000000000200020002",
+                   chain_work.to_string());
+    }
+
+    #[test]
+    fn test_header_check_own_target() {
+        genesis_header().check_own_target().unwrap();
+        let mut header = genesis_header();
+        header.set_nonce(0);
+        assert!(matches!(header.check_own_target(), Err(BlockchainError::HashAboveTarget)));
+    }
+
+    #[test]
+    fn test_validate_pow() {
+        let mainnet = ChainParams::mainnet();
+        let mut header = genesis_header();
+        header.validate_pow(&mainnet).unwrap();
+        assert_eq!("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+                   header.hash().unwrap().to_string());
+
+        header.nonce += 1;
+        assert!(matches!(header.validate_pow(&mainnet), Err(BlockchainError::HashAboveTarget)));
+
+        header.bits = 0x207fffff;
+        assert!(matches!(header.validate_pow(&mainnet), Err(BlockchainError::TargetTooEasy)));
+    }
+
+    #[test]
+    fn test_framing_uses_network_magic() {
+        let block: Block<Script> = Block::new(1, Hash256::default(), &[], 0x207fffff).unwrap();
+        let params = ChainParams::testnet();
+
+        let framed = block.serialize_framed(&params).unwrap();
+        assert_eq!(vec![0x0B, 0x11, 0x09, 0x07], framed[0..4].to_vec());
+        assert_eq!(block.serialize().unwrap().len() as u32,
+                   (&framed[4..8]).read_u32::<LittleEndian>().unwrap());
+
+        let read: Block<Script> = Block::deserialize_framed(&mut framed.as_slice(), &params)
+            .unwrap();
+        assert_eq!(block.header_hash().unwrap(), read.header_hash().unwrap());
+    }
+
+    fn test_block(nonce: u32) -> Block<Transaction> {
+        let input = Input::new(Outpoint::null(), Script::from(vec![0x51]), Sequence::MAX);
+        let outputs = [Output::new(Amount::from_sat(5000000000), Script::from(vec![0x52])),
+                       Output::new(Amount::from_sat(1), Script::new())];
+        let transaction = Transaction::new(1, &[input], &outputs, LockTime::ZERO);
+        let mut block = Block::new(1, Hash256::default(), &[transaction], 0x207fffff).unwrap();
+        block.set_nonce(nonce);
+        block
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let block = test_block(7);
+        let serialized = block.serialize().unwrap();
+        assert_eq!(serialized.len(), block.serialized_size());
+        assert_eq!(block, Block::deserialize(&serialized).unwrap());
+
+        let mut padded = serialized.clone();
+        padded.push(0);
+        let (read, consumed) = Block::<Transaction>::deserialize_partial(&padded).unwrap();
+        assert_eq!(block, read);
+        assert_eq!(serialized.len(), consumed);
+        assert!(matches!(Block::<Transaction>::deserialize(&padded),
+                         Err(BlockchainError::TrailingData(1))));
+    }
+
+    #[test]
+    fn test_multiple_framed_blocks() {
+        let params = ChainParams::regtest();
+        let first = test_block(1);
+        let second = test_block(2);
+        let mut stream = first.serialize_framed(&params).unwrap();
+        stream.extend(second.serialize_framed(&params).unwrap());
+
+        let mut reader = stream.as_slice();
+        assert_eq!(first, Block::deserialize_framed(&mut reader, &params).unwrap());
+        assert_eq!(second, Block::deserialize_framed(&mut reader, &params).unwrap());
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_framing_rejects_other_network() {
+        let block: Block<Script> = Block::new(1, Hash256::default(), &[], 0x207fffff).unwrap();
+        let framed = block.serialize_framed(&ChainParams::mainnet()).unwrap();
+        let result: Result<Block<Script>, BlockchainError> =
+            Block::deserialize_framed(&mut framed.as_slice(), &ChainParams::regtest());
+        match result {
+            Err(BlockchainError::BadMagic { expected, found }) => {
+                assert_eq!(ChainParams::regtest().magic, expected);
+                assert_eq!(ChainParams::mainnet().magic, found);
+            }
+            _ => panic!("expected a bad magic error"),
+        }
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let serialized = genesis_header().serialize().unwrap();
+        let result = BlockHeader::deserialize(&serialized[..40]);
+        assert!(matches!(result, Err(BlockchainError::TruncatedInput)));
+    }
+
+    #[test]
+    fn test_block_builder() {
+        let first = test_block(0).data()[0].clone();
+        let mut second = first.clone();
+        second.set_lock_time(LockTime::from_height(1).unwrap());
+
+        let block = BlockBuilder::new()
+            .previous_hash(Hash256::from([3; 32]))
+            .bits(0x207fffff)
+            .timestamp(1500000000)
+            .push(first.clone())
+            .build()
+            .unwrap();
+        assert_eq!(1500000000, block.header().timestamp());
+        assert_eq!(&Hash256::from([3; 32]), block.header().previous_hash());
+
+        let mut grown = block.clone();
+        grown.push_item(second.clone()).unwrap();
+        assert!(block.header().merkle_root_hash() != grown.header().merkle_root_hash());
+
+        let built = BlockBuilder::new()
+            .previous_hash(Hash256::from([3; 32]))
+            .bits(0x207fffff)
+            .timestamp(1500000000)
+            .push(first)
+            .push(second)
+            .build()
+            .unwrap();
+        assert_eq!(built, grown);
+    }
+
+    #[test]
+    fn test_merkle_root_checked() {
+        let block = test_block(0);
+        block.verify_merkle_root().unwrap();
+        assert_eq!(block.data()[0].txid().unwrap(),
+                   *block.header().merkle_root_hash());
+
+        let mut serialized = block.serialize().unwrap();
+        serialized[36] ^= 1;
+        assert!(matches!(Block::<Transaction>::deserialize(&serialized),
+                         Err(BlockchainError::InvalidMerkleRoot)));
+        let read: Block<Transaction> =
+            Block::deserialize_unchecked(&mut serialized.as_slice(),
+                                         &DeserializeLimits::default())
+                .unwrap();
+        assert!(matches!(read.verify_merkle_root(), Err(BlockchainError::InvalidMerkleRoot)));
+    }
+
+    #[test]
+    fn test_deserialize_limits() {
+        let block = test_block(0);
+        let serialized = block.serialize().unwrap();
+
+        let mut limits = DeserializeLimits {
+            max_block_size: serialized.len(),
+            ..DeserializeLimits::default()
+        };
+        let read: Block<Transaction> =
+            Block::deserialize_with_limits(&mut serialized.as_slice(), &limits).unwrap();
+        assert_eq!(block, read);
+
+        limits.max_block_size = serialized.len() - 1;
+        assert!(matches!(Block::<Transaction>::deserialize_with_limits(&mut serialized.as_slice(),
+                                                                        &limits),
+                         Err(BlockchainError::LimitExceeded { what: "block size", .. })));
+        // Ending right at the limit is a truncated block, not a big one.
+        let truncated = &serialized[..serialized.len() - 1];
+        assert!(matches!(Block::<Transaction>::deserialize_with_limits(&mut &truncated[..],
+                                                                        &limits),
+                         Err(BlockchainError::TruncatedInput)));
+
+        // A transaction count far beyond what could fit in a block.
+        let mut huge_count = serialized[..80].to_vec();
+        huge_count.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert!(matches!(Block::<Transaction>::deserialize(&huge_count),
+                         Err(BlockchainError::LimitExceeded { what: "transaction count", .. })));
+    }
+}
@@ -0,0 +1,1188 @@
+use alloc::collections::BTreeMap;
+use crate::amount::Amount;
+use crate::block::{Block, BlockBuilder, BlockHeader, ChainWork, now};
+use crate::difficulty::{HeaderHistory, RetargetAlgorithm};
+use crate::error::BlockchainError;
+use crate::hash::Hash256;
+use crate::locktime::{LockTime, Sequence};
+use crate::opcodes::{OP_0, OP_1};
+use crate::orphan::OrphanPool;
+use crate::params::ChainParams;
+use crate::prelude::*;
+use crate::script::Script;
+use crate::transaction::{Input, Outpoint, Output, Transaction};
+use crate::util::*;
+
+// The number of previous blocks whose median timestamp a new block must
+// exceed.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+// How far ahead of the clock a block's timestamp may be.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+// The fewest blocks below the tip that pruning keeps, as in Bitcoin Core.
+pub const MIN_BLOCKS_TO_KEEP: u32 = 288;
+
+// A block in the chain, with its height and the total work of the chain
+// up to and including it.
+struct ChainEntry<T: Serializable + Clone> {
+    block: Block<T>,
+    height: u32,
+    chain_work: ChainWork,
+    // Whether the block's data has been dropped, leaving only its header.
+    pruned: bool,
+}
+
+// A tree of blocks starting from a genesis block, with the branch that has
+// the most work as the active chain. Blocks are validated against their
+// branch before they're added.
+pub struct Blockchain<T: Serializable + Clone> {
+    params: ChainParams,
+    entries: BTreeMap<Hash256, ChainEntry<T>>,
+    // Block hashes on the active chain by height.
+    active: Vec<Hash256>,
+    orphans: OrphanPool<T>,
+    // The current time in seconds since the epoch, if there's a clock.
+    clock: Option<fn() -> u32>,
+    max_reorg_depth: Option<u32>,
+    prune_depth: Option<u32>,
+    // Blocks on the active chain below this height have been pruned.
+    pruned_height: u32,
+    listeners: Vec<Box<dyn ChainListener<T>>>,
+}
+
+impl<T: Serializable + Clone> Blockchain<T> {
+    // The genesis block is trusted, apart from its merkle root and
+    // proof-of-work.
+    pub fn new(genesis: Block<T>, params: ChainParams) -> Result<Blockchain<T>, BlockchainError> {
+        genesis.header().validate_pow(&params)?;
+        genesis.verify_merkle_root()?;
+        let hash = genesis.header_hash()?;
+        let mut chain_work = ChainWork::new();
+        chain_work.add_header(genesis.header())?;
+
+        let mut entries = BTreeMap::new();
+        entries.insert(hash,
+                       ChainEntry {
+                           block: genesis,
+                           height: 0,
+                           chain_work: chain_work,
+                           pruned: false,
+                       });
+        Ok(Blockchain {
+               params: params,
+               entries: entries,
+               active: vec![hash],
+               orphans: OrphanPool::default(),
+               clock: if cfg!(feature = "std") { Some(now) } else { None },
+               max_reorg_depth: None,
+               prune_depth: None,
+               pruned_height: 0,
+               listeners: Vec::new(),
+           })
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    // Replaces the clock that timestamps are checked against, e.g. with a
+    // fixed time in tests. Without a clock, which is the default without
+    // std, blocks can be arbitrarily far in the future.
+    pub fn set_clock(&mut self, clock: Option<fn() -> u32>) {
+        self.clock = clock;
+    }
+
+    // Limits how many blocks a reorganization may disconnect. A block that
+    // would take the chain past the limit is still stored, but append()
+    // returns ReorgTooDeep and the chain stays put until reconsider_block()
+    // is called for it.
+    pub fn set_max_reorg_depth(&mut self, max_reorg_depth: Option<u32>) {
+        self.max_reorg_depth = max_reorg_depth;
+    }
+
+    // Drops the data of active blocks more than `prune_depth` blocks below
+    // the tip, keeping their headers. Depths below MIN_BLOCKS_TO_KEEP are
+    // raised to it, and reorganizations deeper than the prune depth are
+    // refused with ReorgTooDeep, even by reconsider_block(), since they
+    // would disconnect pruned blocks.
+    pub fn set_prune_depth(&mut self, prune_depth: Option<u32>) {
+        self.prune_depth = prune_depth.map(|depth| depth.max(MIN_BLOCKS_TO_KEEP));
+        self.prune();
+    }
+
+    // Activates the branch with the most work that contains `hash`, if it
+    // has more work than the active chain, regardless of the reorg limit
+    // but not of the prune depth.
+    pub fn reconsider_block(&mut self, hash: &Hash256) -> Result<ChainUpdate, BlockchainError> {
+        let target = self.entries.get(hash).ok_or(BlockchainError::UnknownBlock)?;
+        let best = self.entries
+            .iter()
+            .filter(|(tip, _)| {
+                        self.ancestor(tip, target.height)
+                            .is_some_and(|entry| entry.block.header() == target.block.header())
+                    })
+            .max_by_key(|(_, entry)| entry.chain_work)
+            .map(|(tip, _)| *tip)
+            .unwrap();
+
+        if self.entries[&best].chain_work <= self.chain_work() {
+            return Ok(ChainUpdate::default());
+        }
+        self.check_reorg_depth(&best, self.prune_depth)?;
+        let update = self.activate(best);
+        self.notify(&update);
+        self.prune();
+        Ok(update)
+    }
+
+    // Registers a listener for changes to the active chain. Listeners are
+    // called in the order they subscribed.
+    pub fn subscribe(&mut self, listener: Box<dyn ChainListener<T>>) {
+        self.listeners.push(listener);
+    }
+
+    // Validates the block and adds it to the chain. It may extend the
+    // active chain or any side branch; if a side branch ends up with more
+    // work than the active chain, the chain reorganizes onto it. A block
+    // whose previous block is unknown is held in the orphan pool and added
+    // once its parent is, with an OrphanBlock error in the meantime.
+    pub fn append(&mut self, block: Block<T>) -> Result<ChainUpdate, BlockchainError> {
+        let hash = block.header_hash()?;
+        if self.entries.contains_key(&hash) || self.orphans.contains(&hash) {
+            return Err(BlockchainError::DuplicateBlock);
+        }
+        if !self.entries.contains_key(block.header().previous_hash()) {
+            // Checking the header first keeps junk out of the pool.
+            block.header().validate_pow(&self.params)?;
+            self.check_future_time(block.header())?;
+            let now = self.clock.map_or(0, |clock| clock());
+            self.orphans.expire(now);
+            self.orphans.insert(block, now)?;
+            return Err(BlockchainError::OrphanBlock);
+        }
+
+        self.add(block)?;
+        let mut best = hash;
+        let mut parents = vec![hash];
+        while let Some(parent) = parents.pop() {
+            for child in self.orphans.take_children(&parent) {
+                // Orphans that turn out to be invalid are dropped.
+                if let Ok(child_hash) = self.add(child) {
+                    if self.entries[&child_hash].chain_work > self.entries[&best].chain_work {
+                        best = child_hash;
+                    }
+                    parents.push(child_hash);
+                }
+            }
+        }
+
+        if self.entries[&best].chain_work <= self.chain_work() {
+            return Ok(ChainUpdate::default());
+        }
+        let limit = self.max_reorg_depth.into_iter().chain(self.prune_depth).min();
+        self.check_reorg_depth(&best, limit)?;
+        let update = self.activate(best);
+        self.notify(&update);
+        self.prune();
+        Ok(update)
+    }
+
+    // The compact target a block on top of `parent` with the given timestamp
+    // has to use, from the chain's retarget algorithm.
+    pub fn next_work_required(&self,
+                              parent: &Hash256,
+                              timestamp: u32)
+                              -> Result<u32, BlockchainError> {
+        let height = self.entries
+            .get(parent)
+            .ok_or(BlockchainError::OrphanBlock)?
+            .height + 1;
+        let branch = Branch {
+            chain: self,
+            tip: *parent,
+        };
+        self.params
+            .retarget
+            .next_work_required(&branch, height, timestamp, &self.params)
+    }
+
+    // The median timestamp of the eleven blocks ending at `hash`, or of all
+    // of them near the start of the chain. A block's timestamp has to be
+    // later than the median time past of its parent.
+    pub fn median_time_past(&self, hash: &Hash256) -> Option<u32> {
+        let mut timestamps: Vec<u32> = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut current = self.entries.get(hash);
+        while let Some(entry) = current {
+            timestamps.push(entry.block.header().timestamp());
+            if timestamps.len() == MEDIAN_TIME_SPAN || entry.height == 0 {
+                break;
+            }
+            current = self.entries.get(entry.block.header().previous_hash());
+        }
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+
+    // The total subsidy of the blocks up to and including `height`, which
+    // the coins in existence can't exceed.
+    pub fn expected_supply(&self, height: u32) -> Amount {
+        let interval = self.params.subsidy_halving_interval as u64;
+        let blocks = height as u64 + 1;
+        let mut supply = 0;
+        let mut era_start = 0;
+        while era_start < blocks {
+            let subsidy = self.params.block_subsidy(era_start as u32).to_sat();
+            if subsidy == 0 {
+                break;
+            }
+            supply += subsidy * (blocks - era_start).min(interval);
+            era_start += interval;
+        }
+        Amount::from_sat(supply)
+    }
+
+    pub fn orphans(&self) -> &OrphanPool<T> {
+        &self.orphans
+    }
+
+    pub fn orphans_mut(&mut self) -> &mut OrphanPool<T> {
+        &mut self.orphans
+    }
+
+    pub fn tip(&self) -> &Block<T> {
+        &self.tip_entry().block
+    }
+
+    pub fn tip_hash(&self) -> Hash256 {
+        *self.active.last().unwrap()
+    }
+
+    // The height of the tip; the genesis block is at height zero.
+    pub fn height(&self) -> u32 {
+        self.tip_entry().height
+    }
+
+    pub fn chain_work(&self) -> ChainWork {
+        self.tip_entry().chain_work
+    }
+
+    // Pruned blocks aren't returned, but their headers are.
+    pub fn get_block_by_hash(&self, hash: &Hash256) -> Option<&Block<T>> {
+        self.entries
+            .get(hash)
+            .filter(|entry| !entry.pruned)
+            .map(|entry| &entry.block)
+    }
+
+    pub fn get_block_by_height(&self, height: u32) -> Option<&Block<T>> {
+        self.active
+            .get(height as usize)
+            .and_then(|hash| self.get_block_by_hash(hash))
+    }
+
+    pub fn get_header_by_hash(&self, hash: &Hash256) -> Option<&BlockHeader> {
+        self.entries.get(hash).map(|entry| entry.block.header())
+    }
+
+    pub fn get_header_by_height(&self, height: u32) -> Option<&BlockHeader> {
+        self.active
+            .get(height as usize)
+            .and_then(|hash| self.get_header_by_hash(hash))
+    }
+
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    // The hashes a getblocks or getheaders message uses to describe the
+    // active chain: the last eleven blocks, then exponentially further apart
+    // back to the genesis block.
+    pub fn block_locator(&self) -> Vec<Hash256> {
+        block_locator(&self.active)
+    }
+
+    // The height of the last block shared with a peer's chain, from the
+    // peer's block locator. Falls back to the genesis block if none of the
+    // hashes are on the active chain.
+    pub fn find_fork_point(&self, locator: &[Hash256]) -> u32 {
+        locator
+            .iter()
+            .find(|hash| self.is_active(hash))
+            .map_or(0, |hash| self.entries[hash].height)
+    }
+
+    // Re-checks the last `depth` blocks of the active chain from the tip
+    // down, or every block if `depth` is 0. Level 0 checks each block's
+    // hash, its link to its parent and its proof-of-work, level 1 adds the
+    // merkle root of unpruned blocks and level 2 and up add the target and
+    // timestamp rules. `progress` is called with the number of blocks
+    // checked so far and the total after each block.
+    pub fn verify<F>(&self, level: u8, depth: u32, mut progress: F) -> Result<(), BlockchainError>
+        where F: FnMut(u32, u32)
+    {
+        let total = match depth {
+            0 => self.height() + 1,
+            depth => depth.min(self.height() + 1),
+        };
+        for checked in 0..total {
+            let height = self.height() - checked;
+            let hash = self.active[height as usize];
+            let entry = &self.entries[&hash];
+            let block = &entry.block;
+            let header = block.header();
+            let corrupt = |reason| {
+                BlockchainError::CorruptBlock {
+                    height: height,
+                    hash: hash,
+                    reason: reason,
+                }
+            };
+
+            if block.header_hash()? != hash {
+                return Err(corrupt("hash does not match"));
+            }
+            if height > 0 && *header.previous_hash() != self.active[height as usize - 1] {
+                return Err(corrupt("previous block is not its parent"));
+            }
+            if header.validate_pow(&self.params).is_err() {
+                return Err(corrupt("invalid proof-of-work"));
+            }
+            if level >= 1 && !entry.pruned && block.verify_merkle_root().is_err() {
+                return Err(corrupt("merkle root does not match"));
+            }
+            if level >= 2 && height > 0 {
+                let previous_hash = header.previous_hash();
+                if self.next_work_required(previous_hash, header.timestamp())? != header.bits() {
+                    return Err(corrupt("incorrect proof-of-work target"));
+                }
+                if header.timestamp() <= self.median_time_past(previous_hash).unwrap() {
+                    return Err(corrupt("timestamp is not after the median time"));
+                }
+            }
+            progress(checked + 1, total);
+        }
+
+        Ok(())
+    }
+
+    // Iterates over the blocks from the genesis block, or the first unpruned
+    // block, to the tip.
+    pub fn iter(&self) -> Blocks<'_, T> {
+        Blocks {
+            chain: self,
+            height: self.pruned_height,
+        }
+    }
+
+    // Validates a block whose parent is known and adds it to the tree,
+    // without changing the active chain.
+    fn add(&mut self, block: Block<T>) -> Result<Hash256, BlockchainError> {
+        let hash = block.header_hash()?;
+        let previous_hash = *block.header().previous_hash();
+        let parent = &self.entries[&previous_hash];
+        let height = parent.height + 1;
+        let mut chain_work = parent.chain_work;
+        block.header().validate_pow(&self.params)?;
+        if block.header().bits() !=
+           self.next_work_required(&previous_hash, block.header().timestamp())? {
+            return Err(BlockchainError::InvalidBlock("incorrect proof-of-work target"));
+        }
+        block.verify_merkle_root()?;
+        if block.header().timestamp() <= self.median_time_past(&previous_hash).unwrap() {
+            return Err(BlockchainError::InvalidBlock("timestamp is not after the median time"));
+        }
+        self.check_future_time(block.header())?;
+
+        chain_work.add_header(block.header())?;
+        self.entries
+            .insert(hash,
+                    ChainEntry {
+                        block: block,
+                        height: height,
+                        chain_work: chain_work,
+                        pruned: false,
+                    });
+
+        Ok(hash)
+    }
+
+    fn tip_entry(&self) -> &ChainEntry<T> {
+        &self.entries[&self.tip_hash()]
+    }
+
+    // Whether the block is on the active chain.
+    fn is_active(&self, hash: &Hash256) -> bool {
+        self.entries
+            .get(hash)
+            .and_then(|entry| self.active.get(entry.height as usize))
+            .is_some_and(|active| active == hash)
+    }
+
+    // The block at `height` on the branch ending at `hash`.
+    fn ancestor(&self, hash: &Hash256, height: u32) -> Option<&ChainEntry<T>> {
+        let mut entry = self.entries.get(hash)?;
+        if height > entry.height {
+            return None;
+        }
+        if self.is_active(hash) {
+            return self.entries.get(&self.active[height as usize]);
+        }
+        while entry.height > height {
+            entry = &self.entries[entry.block.header().previous_hash()];
+        }
+        Some(entry)
+    }
+
+    fn check_future_time(&self, header: &BlockHeader) -> Result<(), BlockchainError> {
+        match self.clock {
+            Some(clock) if header.timestamp() > clock().saturating_add(MAX_FUTURE_BLOCK_TIME) => {
+                Err(BlockchainError::InvalidBlock("timestamp is too far in the future"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_reorg_depth(&self,
+                         tip: &Hash256,
+                         limit: Option<u32>)
+                         -> Result<(), BlockchainError> {
+        if let Some(limit) = limit {
+            let depth = self.height() - self.fork_height(tip);
+            if depth > limit {
+                return Err(BlockchainError::ReorgTooDeep {
+                               depth: depth,
+                               limit: limit,
+                           });
+            }
+        }
+        Ok(())
+    }
+
+    fn prune(&mut self) {
+        let depth = match self.prune_depth {
+            Some(depth) => depth,
+            None => return,
+        };
+        while self.pruned_height + depth <= self.height() {
+            let hash = self.active[self.pruned_height as usize];
+            let entry = self.entries.get_mut(&hash).unwrap();
+            entry.block = Block::from_parts(entry.block.header().clone(), Vec::new());
+            entry.pruned = true;
+            self.pruned_height += 1;
+        }
+    }
+
+    // The height at which the branch ending at `hash` leaves the active
+    // chain.
+    fn fork_height(&self, hash: &Hash256) -> u32 {
+        let mut current = hash;
+        while !self.is_active(current) {
+            current = self.entries[current].block.header().previous_hash();
+        }
+        self.entries[current].height
+    }
+
+    // Makes the branch ending at `hash` the active chain.
+    fn activate(&mut self, hash: Hash256) -> ChainUpdate {
+        let mut connected = Vec::new();
+        let mut current = hash;
+        while !self.is_active(&current) {
+            connected.push(current);
+            current = *self.entries[&current].block.header().previous_hash();
+        }
+        connected.reverse();
+
+        let fork_height = self.entries[&current].height as usize;
+        let mut disconnected = self.active.split_off(fork_height + 1);
+        disconnected.reverse();
+        self.active.extend_from_slice(&connected);
+
+        ChainUpdate {
+            disconnected: disconnected,
+            connected: connected,
+        }
+    }
+
+    fn notify(&mut self, update: &ChainUpdate) {
+        if self.listeners.is_empty() {
+            return;
+        }
+        let mut events = Vec::new();
+        for hash in &update.disconnected {
+            let entry = &self.entries[hash];
+            events.push(ChainEvent::BlockDisconnected {
+                            block: &entry.block,
+                            height: entry.height,
+                        });
+        }
+        for hash in &update.connected {
+            let entry = &self.entries[hash];
+            events.push(ChainEvent::BlockConnected {
+                            block: &entry.block,
+                            height: entry.height,
+                        });
+            for transaction in entry.block.data() {
+                events.push(ChainEvent::TransactionConfirmed {
+                                transaction: transaction,
+                                block_hash: hash,
+                                height: entry.height,
+                            });
+            }
+        }
+        for event in &events {
+            for listener in self.listeners.iter_mut() {
+                listener.on_event(event);
+            }
+        }
+    }
+}
+
+// A change to the active chain. Events for one append arrive in the same
+// order as the ChainUpdate: disconnections from the old tip down, then
+// each connected block followed by its transactions.
+#[derive(Debug)]
+pub enum ChainEvent<'a, T: Serializable + Clone> {
+    BlockConnected { block: &'a Block<T>, height: u32 },
+    BlockDisconnected { block: &'a Block<T>, height: u32 },
+    TransactionConfirmed {
+        transaction: &'a T,
+        block_hash: &'a Hash256,
+        height: u32,
+    },
+}
+
+pub trait ChainListener<T: Serializable + Clone>: Send {
+    fn on_event(&mut self, event: &ChainEvent<'_, T>);
+}
+
+impl<T, F> ChainListener<T> for F
+    where T: Serializable + Clone,
+          F: FnMut(&ChainEvent<'_, T>) + Send
+{
+    fn on_event(&mut self, event: &ChainEvent<'_, T>) {
+        self(event)
+    }
+}
+
+// The block locator of a chain given its block hashes by height, spaced as
+// in Bitcoin Core's LocatorEntries.
+pub(crate) fn block_locator(chain: &[Hash256]) -> Vec<Hash256> {
+    let mut locator = Vec::new();
+    let mut height = chain.len() - 1;
+    let mut step = 1;
+    loop {
+        locator.push(chain[height]);
+        if height == 0 {
+            break;
+        }
+        height = height.saturating_sub(step);
+        if locator.len() > 10 {
+            step *= 2;
+        }
+    }
+    locator
+}
+
+// The blocks that left and joined the active chain when a block was
+// appended. Disconnected blocks are listed from the old tip down, and
+// connected blocks from the fork point up, so callers can undo and apply
+// them in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainUpdate {
+    pub disconnected: Vec<Hash256>,
+    pub connected: Vec<Hash256>,
+}
+
+// The branch of a chain ending at `tip`, as seen by retarget algorithms.
+struct Branch<'a, T: Serializable + Clone> {
+    chain: &'a Blockchain<T>,
+    tip: Hash256,
+}
+
+impl<T: Serializable + Clone> HeaderHistory for Branch<'_, T> {
+    fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        self.chain
+            .ancestor(&self.tip, height)
+            .map(|entry| entry.block.header())
+    }
+}
+
+impl Blockchain<Transaction> {
+    // Mines `count` blocks on the tip, each paying its subsidy to
+    // `script_pubkey`, like bitcoind's generatetoaddress. As there, each
+    // block is timestamped with the clock, or just after the median time past
+    // if that's later. Without a clock the timestamps are spaced evenly after
+    // the tip's. A fixed clock makes the blocks deterministic. The nonce
+    // search only finishes quickly with regtest's easy targets. If a block
+    // can't be mined or appended, the ones before it stay on the chain.
+    pub fn generate_to_script(&mut self,
+                              count: u32,
+                              script_pubkey: &Script)
+                              -> Result<Vec<Hash256>, BlockchainError> {
+        let mut hashes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let parent = self.tip_hash();
+            let height = self.height() + 1;
+            let timestamp = match self.clock {
+                Some(clock) => {
+                    self.median_time_past(&parent)
+                        .unwrap()
+                        .checked_add(1)
+                        .map(|earliest| earliest.max(clock()))
+                }
+                None => {
+                    self.tip()
+                        .header()
+                        .timestamp()
+                        .checked_add(self.params.pow_target_spacing)
+                }
+            };
+            let timestamp =
+                timestamp.ok_or(BlockchainError::InvalidBlock("no timestamps left to mine with"))?;
+            let input = Input::new(Outpoint::null(), coinbase_script(height), Sequence::MAX);
+            let output = Output::new(self.params.block_subsidy(height), script_pubkey.clone());
+            let coinbase = Transaction::new(1, &[input], &[output], LockTime::ZERO);
+            let mut block = BlockBuilder::new()
+                .version(4)
+                .previous_hash(parent)
+                .bits(self.next_work_required(&parent, timestamp)?)
+                .timestamp(timestamp)
+                .push(coinbase)
+                .build()?;
+            // The timestamp stays put, since on chains with minimum-difficulty
+            // blocks a later one can change the bits required.
+            loop {
+                match block.header().validate_pow(&self.params) {
+                    Ok(()) => break,
+                    Err(BlockchainError::HashAboveTarget) => {}
+                    Err(error) => return Err(error),
+                }
+                let nonce = block.header().nonce().wrapping_add(1);
+                if nonce == 0 {
+                    return Err(BlockchainError::InvalidBlock("no nonce meets the target"));
+                }
+                block.set_nonce(nonce);
+            }
+            hashes.push(block.header_hash()?);
+            self.append(block)?;
+        }
+
+        Ok(hashes)
+    }
+}
+
+// Whether the coinbase of the block at `height` can be spent in a block at
+// `spending_height`.
+pub fn is_coinbase_mature(height: u32, spending_height: u32, params: &ChainParams) -> bool {
+    spending_height >= height.saturating_add(params.coinbase_maturity)
+}
+
+// The BIP34 height push, followed by a zero so the script is at least the
+// two bytes a coinbase script needs.
+fn coinbase_script(height: u32) -> Script {
+    let mut script = Vec::new();
+    match height {
+        0 => script.push(OP_0),
+        1..=16 => script.push(OP_1 + height as u8 - 1),
+        _ => {
+            let mut number = Vec::new();
+            let mut value = height;
+            while value > 0 {
+                number.push(value as u8);
+                value >>= 8;
+            }
+            if number.last().is_some_and(|byte| byte & 0x80 != 0) {
+                number.push(0);
+            }
+            script.push(number.len() as u8);
+            script.extend_from_slice(&number);
+        }
+    }
+    script.push(OP_0);
+    Script::from(script)
+}
+
+pub struct Blocks<'a, T: Serializable + Clone> {
+    chain: &'a Blockchain<T>,
+    height: u32,
+}
+
+impl<'a, T: Serializable + Clone> Iterator for Blocks<'a, T> {
+    type Item = &'a Block<T>;
+
+    fn next(&mut self) -> Option<&'a Block<T>> {
+        let block = self.chain.get_block_by_height(self.height)?;
+        self.height += 1;
+        Some(block)
+    }
+}
+
+impl<'a, T: Serializable + Clone> IntoIterator for &'a Blockchain<T> {
+    type Item = &'a Block<T>;
+    type IntoIter = Blocks<'a, T>;
+
+    fn into_iter(self) -> Blocks<'a, T> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::{compact_to_target, target_to_compact};
+    use crate::genesis::{GenesisBuilder, genesis_block};
+    use crate::params::Network;
+    use crate::uint::U256;
+    use std::sync::{Arc, Mutex};
+
+    fn coinbase(height: u32) -> Transaction {
+        let input = Input::new(Outpoint::null(),
+                               Script::from(height.to_le_bytes().to_vec()),
+                               Sequence::MAX);
+        let output = Output::new(Amount::from_sat(5000000000), Script::from(vec![0x51]));
+        Transaction::new(1, &[input], &[output], LockTime::ZERO)
+    }
+
+    // Mines a regtest block on top of `previous_hash`.
+    fn mine(previous_hash: Hash256, height: u32, timestamp: u32) -> Block<Transaction> {
+        mine_with_bits(previous_hash, height, timestamp, 0x207fffff)
+    }
+
+    fn mine_with_bits(previous_hash: Hash256,
+                      height: u32,
+                      timestamp: u32,
+                      bits: u32)
+                      -> Block<Transaction> {
+        let mut block = BlockBuilder::new()
+            .previous_hash(previous_hash)
+            .bits(bits)
+            .timestamp(timestamp)
+            .push(coinbase(height))
+            .build()
+            .unwrap();
+        while block.header().validate_pow(&ChainParams::regtest()).is_err() {
+            let nonce = block.header().nonce() + 1;
+            block.set_nonce(nonce);
+        }
+        block
+    }
+
+    fn test_chain(length: u32) -> Blockchain<Transaction> {
+        let genesis = mine(Hash256::default(), 0, 1500000000);
+        let mut chain = Blockchain::new(genesis, ChainParams::regtest()).unwrap();
+        for height in 1..length {
+            let block = mine(chain.tip_hash(), height, 1500000000 + height * 600);
+            chain.append(block).unwrap();
+        }
+        chain
+    }
+
+    #[test]
+    fn test_append_and_lookup() {
+        let chain = test_chain(4);
+        assert_eq!(3, chain.height());
+        assert_eq!(chain.tip_hash(), chain.tip().header_hash().unwrap());
+        let second = chain.get_block_by_height(1).unwrap();
+        assert_eq!(second,
+                   chain
+                       .get_block_by_hash(&second.header_hash().unwrap())
+                       .unwrap());
+        assert!(chain.get_block_by_height(4).is_none());
+        assert!(chain.get_block_by_hash(&Hash256::default()).is_none());
+
+        let timestamps: Vec<u32> = chain
+            .iter()
+            .map(|block| block.header().timestamp())
+            .collect();
+        assert_eq!(vec![1500000000, 1500000600, 1500001200, 1500001800], timestamps);
+
+        let headers: Vec<_> = chain.iter().map(|block| block.header().clone()).collect();
+        assert_eq!(ChainWork::from_headers(&headers).unwrap(), chain.chain_work());
+    }
+
+    #[test]
+    fn test_append_rejects_invalid_blocks() {
+        let mut chain = test_chain(3);
+        let tip = chain.tip_hash();
+
+        let orphan = mine(Hash256::from([1; 32]), 3, 1500005000);
+        assert!(matches!(chain.append(orphan), Err(BlockchainError::OrphanBlock)));
+        assert!(matches!(chain.append(chain.tip().clone()),
+                         Err(BlockchainError::DuplicateBlock)));
+
+        let mut unmined = mine(tip, 3, 1500005000);
+        while unmined.header().validate_pow(chain.params()).is_ok() {
+            let nonce = unmined.header().nonce() + 1;
+            unmined.set_nonce(nonce);
+        }
+        assert!(matches!(chain.append(unmined), Err(BlockchainError::HashAboveTarget)));
+
+        // The median of the three timestamps so far is the second one.
+        let early = mine(tip, 3, 1500000600);
+        assert!(matches!(chain.append(early), Err(BlockchainError::InvalidBlock(_))));
+        chain.append(mine(tip, 3, 1500000601)).unwrap();
+        assert_eq!(3, chain.height());
+    }
+
+    #[test]
+    fn test_timestamp_rules() {
+        fn clock() -> u32 {
+            1500010000
+        }
+
+        let mut chain = test_chain(4);
+        assert_eq!(Some(1500001200), chain.median_time_past(&chain.tip_hash()));
+        assert_eq!(None, chain.median_time_past(&Hash256::default()));
+
+        chain.set_clock(Some(clock));
+        let tip = chain.tip_hash();
+        let too_new = mine(tip, 4, clock() + MAX_FUTURE_BLOCK_TIME + 1);
+        assert!(matches!(chain.append(too_new), Err(BlockchainError::InvalidBlock(_))));
+        chain
+            .append(mine(tip, 4, clock() + MAX_FUTURE_BLOCK_TIME))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reorganization() {
+        let mut chain = test_chain(3);
+        let fork = chain.get_block_by_height(1).unwrap().header_hash().unwrap();
+        let old_tip = chain.tip_hash();
+
+        // A side branch with as much work as the active chain doesn't
+        // replace it.
+        let side = mine(fork, 2, 1500005000);
+        let side_hash = side.header_hash().unwrap();
+        assert_eq!(ChainUpdate::default(), chain.append(side).unwrap());
+        assert_eq!(old_tip, chain.tip_hash());
+        assert!(chain.contains(&side_hash));
+
+        let next = mine(side_hash, 3, 1500005600);
+        let next_hash = next.header_hash().unwrap();
+        assert_eq!(ChainUpdate {
+                       disconnected: vec![old_tip],
+                       connected: vec![side_hash, next_hash],
+                   },
+                   chain.append(next).unwrap());
+        assert_eq!(3, chain.height());
+        assert_eq!(next_hash, chain.tip_hash());
+        assert_eq!(side_hash,
+                   chain.get_block_by_height(2).unwrap().header_hash().unwrap());
+        assert!(chain.get_block_by_hash(&old_tip).is_some());
+
+        let extension = mine(next_hash, 4, 1500006200);
+        let extension_hash = extension.header_hash().unwrap();
+        assert_eq!(ChainUpdate {
+                       disconnected: vec![],
+                       connected: vec![extension_hash],
+                   },
+                   chain.append(extension).unwrap());
+        assert_eq!(5, chain.iter().count());
+    }
+
+    #[test]
+    fn test_max_reorg_depth() {
+        let mut chain = test_chain(4);
+        chain.set_max_reorg_depth(Some(1));
+        let old_tip = chain.tip_hash();
+        let fork = chain.get_block_by_height(1).unwrap().header_hash().unwrap();
+
+        let mut previous = fork;
+        let mut side = Vec::new();
+        for height in 2..5 {
+            let block = mine(previous, height, 1500005000 + height * 600);
+            previous = block.header_hash().unwrap();
+            side.push(previous);
+            let result = chain.append(block);
+            if height < 4 {
+                assert_eq!(ChainUpdate::default(), result.unwrap());
+            } else {
+                assert!(matches!(result,
+                                 Err(BlockchainError::ReorgTooDeep { depth: 2, limit: 1 })));
+            }
+        }
+        assert_eq!(old_tip, chain.tip_hash());
+        assert!(chain.contains(&previous));
+
+        assert_eq!(ChainUpdate {
+                       disconnected: vec![old_tip,
+                                          chain.get_block_by_height(2)
+                                              .unwrap()
+                                              .header_hash()
+                                              .unwrap()],
+                       connected: side.clone(),
+                   },
+                   chain.reconsider_block(&side[0]).unwrap());
+        assert_eq!(previous, chain.tip_hash());
+        assert_eq!(ChainUpdate::default(), chain.reconsider_block(&fork).unwrap());
+        assert!(matches!(chain.reconsider_block(&Hash256::default()),
+                         Err(BlockchainError::UnknownBlock)));
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut chain = test_chain(5);
+        let mut checked = Vec::new();
+        chain.verify(2, 0, |done, total| checked.push((done, total))).unwrap();
+        assert_eq!(vec![(1, 5), (2, 5), (3, 5), (4, 5), (5, 5)], checked);
+
+        // Swapping a block's transactions breaks only its merkle root.
+        let hash = chain.active[2];
+        let entry = chain.entries.get_mut(&hash).unwrap();
+        entry.block = Block::from_parts(entry.block.header().clone(), vec![coinbase(7)]);
+        chain.verify(0, 0, |_, _| {}).unwrap();
+        chain.verify(1, 2, |_, _| {}).unwrap();
+        assert!(matches!(chain.verify(1, 3, |_, _| {}),
+                         Err(BlockchainError::CorruptBlock { height: 2, .. })));
+
+        chain.entries.get_mut(&hash).unwrap().block.set_nonce(1);
+        match chain.verify(0, 0, |_, _| {}) {
+            Err(BlockchainError::CorruptBlock { height, hash: found, reason }) => {
+                assert_eq!(2, height);
+                assert_eq!(hash, found);
+                assert_eq!("hash does not match", reason);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_chain_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = test_chain(3);
+        let recorder = events.clone();
+        chain.subscribe(Box::new(move |event: &ChainEvent<'_, Transaction>| {
+            let event = match event {
+                ChainEvent::BlockConnected { height, .. } => ("connected", *height),
+                ChainEvent::BlockDisconnected { height, .. } => ("disconnected", *height),
+                ChainEvent::TransactionConfirmed { height, .. } => ("confirmed", *height),
+            };
+            recorder.lock().unwrap().push(event);
+        }));
+
+        let fork = chain.get_block_by_height(1).unwrap().header_hash().unwrap();
+        let side = mine(fork, 2, 1500005000);
+        let side_hash = side.header_hash().unwrap();
+        chain.append(side).unwrap();
+        assert!(events.lock().unwrap().is_empty());
+
+        chain.append(mine(side_hash, 3, 1500005600)).unwrap();
+        assert_eq!(vec![("disconnected", 2),
+                        ("connected", 2),
+                        ("confirmed", 2),
+                        ("connected", 3),
+                        ("confirmed", 3)],
+                   *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_pruning() {
+        let mut chain = test_chain(300);
+        let fork = chain.get_block_by_height(11).unwrap().header_hash().unwrap();
+        chain.set_prune_depth(Some(10));
+        assert!(chain.get_block_by_height(11).is_none());
+        assert!(chain.get_block_by_hash(&fork).is_none());
+        assert_eq!(chain.get_header_by_hash(&fork),
+                   chain.get_header_by_height(11));
+        assert!(chain.get_block_by_height(12).is_some());
+        assert_eq!(MIN_BLOCKS_TO_KEEP as usize, chain.iter().count());
+        chain.verify(2, 0, |_, _| {}).unwrap();
+
+        let block = mine(chain.tip_hash(), 300, 1500180000);
+        chain.append(block).unwrap();
+        assert!(chain.get_block_by_height(12).is_none());
+
+        // A branch from a pruned block can't replace the active chain.
+        let mut previous = fork;
+        for height in 12..302 {
+            let block = mine(previous, height, 1500000001 + height * 600);
+            previous = block.header_hash().unwrap();
+            let result = chain.append(block);
+            if height < 301 {
+                assert_eq!(ChainUpdate::default(), result.unwrap());
+            } else {
+                assert!(matches!(result,
+                                 Err(BlockchainError::ReorgTooDeep { depth: 289, limit: 288 })));
+            }
+        }
+        assert!(matches!(chain.reconsider_block(&previous),
+                         Err(BlockchainError::ReorgTooDeep { .. })));
+        assert_eq!(300, chain.height());
+    }
+
+    #[test]
+    fn test_orphans() {
+        let mut chain = test_chain(2);
+        let first = mine(chain.tip_hash(), 2, 1500005000);
+        let first_hash = first.header_hash().unwrap();
+        let second = mine(first_hash, 3, 1500005600);
+        let second_hash = second.header_hash().unwrap();
+        let third = mine(second_hash, 4, 1500006200);
+        let third_hash = third.header_hash().unwrap();
+        // Fails the median time rule once its parent arrives.
+        let invalid = mine(second_hash, 4, 1);
+
+        assert!(matches!(chain.append(third), Err(BlockchainError::OrphanBlock)));
+        assert!(matches!(chain.append(invalid), Err(BlockchainError::OrphanBlock)));
+        assert!(matches!(chain.append(second.clone()), Err(BlockchainError::OrphanBlock)));
+        assert!(matches!(chain.append(second), Err(BlockchainError::DuplicateBlock)));
+        assert_eq!(3, chain.orphans().len());
+
+        assert_eq!(ChainUpdate {
+                       disconnected: vec![],
+                       connected: vec![first_hash, second_hash, third_hash],
+                   },
+                   chain.append(first).unwrap());
+        assert_eq!(4, chain.height());
+        assert!(chain.orphans().is_empty());
+    }
+
+    #[test]
+    fn test_retarget() {
+        // Retarget every four blocks, without the testnet exception.
+        let params = ChainParams {
+            pow_target_timespan: 4 * 600,
+            pow_allow_min_difficulty_blocks: false,
+            pow_no_retargeting: false,
+            ..ChainParams::regtest()
+        };
+        let genesis = mine(Hash256::default(), 0, 1500000000);
+        let mut chain = Blockchain::new(genesis, params).unwrap();
+        for height in 1..4 {
+            let block = mine(chain.tip_hash(), height, 1500000000 + height * 600);
+            chain.append(block).unwrap();
+        }
+
+        // The last three blocks took 1800 seconds rather than 2400.
+        let tip = chain.tip_hash();
+        let bits = chain.next_work_required(&tip, 1500002400).unwrap();
+        assert_eq!(target_to_compact(compact_to_target(0x207fffff).unwrap() /
+                                     U256::from(4) * U256::from(3)),
+                   bits);
+        let stale_target = mine(tip, 4, 1500002400);
+        assert!(matches!(chain.append(stale_target), Err(BlockchainError::InvalidBlock(_))));
+        chain
+            .append(mine_with_bits(tip, 4, 1500002400, bits))
+            .unwrap();
+        assert_eq!(bits,
+                   chain
+                       .next_work_required(&chain.tip_hash(), 1500003000)
+                       .unwrap());
+    }
+
+    #[test]
+    fn test_generate_to_script() {
+        fn clock() -> u32 {
+            1500000000
+        }
+
+        let mut chain = test_chain(1);
+        chain.set_clock(Some(clock));
+        let script_pubkey = Script::from(vec![0x51]);
+        let hashes = chain.generate_to_script(151, &script_pubkey).unwrap();
+        assert_eq!(151, hashes.len());
+        assert_eq!(151, chain.height());
+        assert_eq!(chain.tip_hash(), hashes[150]);
+
+        let coinbase = |height| chain.get_block_by_height(height).unwrap().data()[0].clone();
+        assert_eq!(Amount::from_sat(5000000000), coinbase(149).outputs()[0].value());
+        assert_eq!(Amount::from_sat(2500000000), coinbase(150).outputs()[0].value());
+        assert_eq!(&script_pubkey, coinbase(1).outputs()[0].script());
+        // 151 needs a sign byte.
+        assert_eq!(&[0x02, 0x97, 0x00, 0x00][..],
+                   coinbase(151).inputs()[0].script().as_bytes());
+
+        // The clock stays put, so each block is just after the median time.
+        for height in 1..152 {
+            let header = chain.get_header_by_height(height).unwrap();
+            let median = chain.median_time_past(header.previous_hash()).unwrap();
+            assert_eq!(median + 1, header.timestamp());
+        }
+
+        // Generating with the same clock is deterministic.
+        let mut other = test_chain(1);
+        other.set_clock(Some(clock));
+        assert_eq!(hashes, other.generate_to_script(151, &script_pubkey).unwrap());
+
+        // Without a clock, blocks are spaced evenly after the tip.
+        let mut other = test_chain(1);
+        other.set_clock(None);
+        other.generate_to_script(2, &script_pubkey).unwrap();
+        assert_eq!(1500001200, other.tip().header().timestamp());
+
+        // There's no timestamp after the last one to give a new block.
+        let genesis = mine(Hash256::default(), 0, u32::MAX - 100);
+        let mut late = Blockchain::new(genesis, ChainParams::regtest()).unwrap();
+        late.set_clock(None);
+        assert!(matches!(late.generate_to_script(1, &script_pubkey),
+                         Err(BlockchainError::InvalidBlock(_))));
+
+        assert!(!is_coinbase_mature(1, 100, chain.params()));
+        assert!(is_coinbase_mature(1, 101, chain.params()));
+        let params = ChainParams {
+            coinbase_maturity: 10,
+            ..ChainParams::regtest()
+        };
+        assert!(is_coinbase_mature(1, 11, &params));
+    }
+
+    // Enough blocks to mature a coinbase, starting from the clock's time,
+    // which spacing them by the target would push too far into the future.
+    #[test]
+    fn test_generate_from_current_time() {
+        fn clock() -> u32 {
+            1700000000
+        }
+
+        let genesis = GenesisBuilder::new().timestamp(clock()).build().unwrap();
+        let mut chain = Blockchain::new(genesis, ChainParams::regtest()).unwrap();
+        chain.set_clock(Some(clock));
+        let hashes = chain.generate_to_script(101, &Script::from(vec![0x51])).unwrap();
+        assert_eq!(101, hashes.len());
+        assert!(chain.tip().header().timestamp() <= clock() + MAX_FUTURE_BLOCK_TIME);
+    }
+
+    #[test]
+    fn test_subsidy() {
+        let params = ChainParams::mainnet();
+        assert_eq!(Amount::from_sat(5000000000), params.block_subsidy(209999));
+        assert_eq!(Amount::from_sat(2500000000), params.block_subsidy(210000));
+        assert_eq!(Amount::from_sat(1), params.block_subsidy(32 * 210000));
+        assert_eq!(Amount::ZERO, params.block_subsidy(33 * 210000));
+        assert_eq!(Amount::ZERO, params.block_subsidy(u32::MAX));
+
+        let chain = Blockchain::new(genesis_block(Network::Mainnet), params).unwrap();
+        assert_eq!(Amount::from_sat(5000000000), chain.expected_supply(0));
+        assert_eq!(Amount::from_sat(210000 * 5000000000), chain.expected_supply(209999));
+        assert_eq!(Amount::from_sat(210000 * 5000000000 + 2500000000),
+                   chain.expected_supply(210000));
+        assert_eq!(Amount::from_sat(2099999997690000), chain.expected_supply(u32::MAX));
+    }
+
+    #[test]
+    fn test_block_locator() {
+        let mut chain = test_chain(30);
+        let hash_at = |chain: &Blockchain<Transaction>, height| {
+            chain.get_block_by_height(height).unwrap().header_hash().unwrap()
+        };
+        let heights = [29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 16, 12, 4, 0];
+        let expected: Vec<Hash256> = heights
+            .iter()
+            .map(|height| hash_at(&chain, *height))
+            .collect();
+        let locator = chain.block_locator();
+        assert_eq!(expected, locator);
+        assert_eq!(29, chain.find_fork_point(&locator));
+
+        // A peer whose chain branched off after block 20. Blocks the chain
+        // knows about but that aren't active don't count.
+        let side = mine(hash_at(&chain, 20), 21, 1500100000);
+        let side_hash = side.header_hash().unwrap();
+        chain.append(side).unwrap();
+        let mut peer_locator = vec![Hash256::from([7; 32]), side_hash];
+        peer_locator.extend_from_slice(&locator[9..]);
+        assert_eq!(20, chain.find_fork_point(&peer_locator));
+        assert_eq!(0, chain.find_fork_point(&[Hash256::from([7; 32])]));
+    }
+}
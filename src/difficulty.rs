@@ -0,0 +1,401 @@
+use alloc::sync::Arc;
+use core::fmt;
+use crate::block::{BlockHeader, compact_to_target, target_to_compact};
+use crate::error::BlockchainError;
+use crate::params::ChainParams;
+use crate::uint::U256;
+
+// The blocks of the branch a new block is being added to.
+pub trait HeaderHistory {
+    // The header at `height`, or None above the new block's parent.
+    fn header_at(&self, height: u32) -> Option<&BlockHeader>;
+}
+
+// A difficulty adjustment algorithm: the compact target a block at `height`
+// with the given timestamp has to use. Chains pick one through
+// ChainParams::retarget.
+pub trait RetargetAlgorithm: fmt::Debug + Send + Sync {
+    fn next_work_required(&self,
+                          history: &dyn HeaderHistory,
+                          height: u32,
+                          timestamp: u32,
+                          params: &ChainParams)
+                          -> Result<u32, BlockchainError>;
+}
+
+// The retarget algorithm of a chain. The built-in ones compare by value,
+// and custom ones by whether they share an allocation.
+#[derive(Clone, Debug)]
+pub enum Retarget {
+    Bitcoin,
+    Lwma(Lwma),
+    Asert(Asert),
+    Custom(Arc<dyn RetargetAlgorithm>),
+}
+
+impl PartialEq for Retarget {
+    fn eq(&self, other: &Retarget) -> bool {
+        match (self, other) {
+            (Retarget::Bitcoin, Retarget::Bitcoin) => true,
+            (Retarget::Lwma(a), Retarget::Lwma(b)) => a == b,
+            (Retarget::Asert(a), Retarget::Asert(b)) => a == b,
+            (Retarget::Custom(a), Retarget::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl RetargetAlgorithm for Retarget {
+    fn next_work_required(&self,
+                          history: &dyn HeaderHistory,
+                          height: u32,
+                          timestamp: u32,
+                          params: &ChainParams)
+                          -> Result<u32, BlockchainError> {
+        let algorithm: &dyn RetargetAlgorithm = match self {
+            Retarget::Bitcoin => &Bitcoin,
+            Retarget::Lwma(lwma) => lwma,
+            Retarget::Asert(asert) => asert,
+            Retarget::Custom(custom) => custom.as_ref(),
+        };
+        algorithm.next_work_required(history, height, timestamp, params)
+    }
+}
+
+// Bitcoin's retarget every difficulty_adjustment_interval() blocks, as in
+// Bitcoin Core's GetNextWorkRequired, including testnet's minimum-difficulty
+// exception.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bitcoin;
+
+impl RetargetAlgorithm for Bitcoin {
+    fn next_work_required(&self,
+                          history: &dyn HeaderHistory,
+                          height: u32,
+                          timestamp: u32,
+                          params: &ChainParams)
+                          -> Result<u32, BlockchainError> {
+        let parent_height = height.checked_sub(1).ok_or(BlockchainError::OrphanBlock)?;
+        let last = parent(history, parent_height)?;
+        let interval = params.difficulty_adjustment_interval();
+        if !height.is_multiple_of(interval) {
+            if !params.pow_allow_min_difficulty_blocks {
+                return Ok(last.bits());
+            }
+            let pow_limit = target_to_compact(params.pow_limit);
+            let spacing = u64::from(params.pow_target_spacing);
+            if u64::from(timestamp) > u64::from(last.timestamp()) + spacing * 2 {
+                return Ok(pow_limit);
+            }
+            // Otherwise use the last target that wasn't a minimum-difficulty
+            // exception.
+            let mut height = parent_height;
+            let mut header = last;
+            while !height.is_multiple_of(interval) && header.bits() == pow_limit {
+                height -= 1;
+                header = parent(history, height)?;
+            }
+            return Ok(header.bits());
+        }
+
+        let first = parent(history, height.saturating_sub(interval))?;
+        calculate_next_work_required(last.bits(), first.timestamp(), last.timestamp(), params)
+    }
+}
+
+// Bitcoin's retarget, as in Bitcoin Core's CalculateNextWorkRequired: the
+// last target scaled by how long the period actually took, with the
+// timespan clamped to a factor of four either way and the result to the
+// proof-of-work limit. The timestamps are those of the first and last
+// blocks of the period.
+pub fn calculate_next_work_required(last_bits: u32,
+                                    first_timestamp: u32,
+                                    last_timestamp: u32,
+                                    params: &ChainParams)
+                                    -> Result<u32, BlockchainError> {
+    if params.pow_no_retargeting {
+        return Ok(last_bits);
+    }
+
+    let timespan = params.pow_target_timespan as i64;
+    let actual = (last_timestamp as i64 - first_timestamp as i64).clamp(timespan / 4,
+                                                                        timespan * 4);
+    let target = scale(compact_to_target(last_bits)?,
+                       U256::from(actual as u64),
+                       U256::from(timespan as u64),
+                       params);
+
+    Ok(target_to_compact(target))
+}
+
+// Zawy's linearly weighted moving average (LWMA-1): the average target of
+// the last `window` blocks, scaled by their solve times with the most
+// recent weighted heaviest. Suits chains with short block times, where it
+// reacts within a few blocks. Until the chain is `window` blocks long,
+// blocks use the proof-of-work limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lwma {
+    pub window: u32,
+}
+
+impl RetargetAlgorithm for Lwma {
+    fn next_work_required(&self,
+                          history: &dyn HeaderHistory,
+                          height: u32,
+                          _timestamp: u32,
+                          params: &ChainParams)
+                          -> Result<u32, BlockchainError> {
+        if params.pow_no_retargeting {
+            let parent_height = height.checked_sub(1).ok_or(BlockchainError::OrphanBlock)?;
+            return Ok(parent(history, parent_height)?.bits());
+        }
+        if height <= self.window {
+            return Ok(target_to_compact(params.pow_limit));
+        }
+
+        let window = self.window as u64;
+        let spacing = params.pow_target_spacing as u64;
+        // The weighted solve times are normalized by k, which is what they
+        // add up to when every block takes exactly the target spacing.
+        let k = window * (window + 1) * spacing / 2;
+        let start = height - self.window - 1;
+        let mut previous_timestamp = parent(history, start)?.timestamp() as u64;
+        let mut weighted_solve_times: u64 = 0;
+        // The average is accumulated as quotients and remainders so that
+        // the sum can't overflow.
+        let mut average_target = U256::ZERO;
+        let mut remainders = U256::ZERO;
+        for (weight, block_height) in (start + 1..height).enumerate() {
+            let header = parent(history, block_height)?;
+            // Out-of-order timestamps count as one-second solve times, and
+            // long gaps are capped so a stall doesn't crash the difficulty.
+            let timestamp = (header.timestamp() as u64).max(previous_timestamp + 1);
+            let solve_time = (timestamp - previous_timestamp).min(6 * spacing);
+            previous_timestamp = timestamp;
+            weighted_solve_times += solve_time * (weight as u64 + 1);
+            let (quotient, remainder) = compact_to_target(header.bits())?
+                .checked_div_rem(U256::from(window))
+                .ok_or(BlockchainError::InvalidTarget)?;
+            average_target = average_target + quotient;
+            remainders = remainders + remainder;
+        }
+        average_target = average_target + remainders / U256::from(window);
+
+        let target = scale(average_target,
+                           U256::from(weighted_solve_times),
+                           U256::from(k),
+                           params);
+        Ok(target_to_compact(target))
+    }
+}
+
+// The absolutely scheduled exponentially rising targets algorithm
+// (aserti3-2d), as used by Bitcoin Cash. The target follows from the anchor
+// block alone: it doubles for every `half_life` seconds the chain falls
+// behind schedule since the anchor, and halves for every `half_life` it
+// gets ahead. Blocks up to the anchor use the anchor's bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Asert {
+    pub anchor_height: u32,
+    pub anchor_bits: u32,
+    // The timestamp of the anchor's parent block.
+    pub anchor_parent_timestamp: u32,
+    pub half_life: u32,
+}
+
+impl RetargetAlgorithm for Asert {
+    fn next_work_required(&self,
+                          history: &dyn HeaderHistory,
+                          height: u32,
+                          _timestamp: u32,
+                          params: &ChainParams)
+                          -> Result<u32, BlockchainError> {
+        if params.pow_no_retargeting || height <= self.anchor_height {
+            return Ok(self.anchor_bits);
+        }
+
+        let parent_height = height - 1;
+        let time_delta = parent(history, parent_height)?.timestamp() as i64 -
+                         self.anchor_parent_timestamp as i64;
+        let height_delta = (parent_height - self.anchor_height) as i64;
+        let spacing = params.pow_target_spacing as i64;
+        // A 16.16 fixed-point exponent of two.
+        let exponent = (time_delta - spacing * (height_delta + 1)) * 65536 /
+                       self.half_life as i64;
+        let shifts = exponent >> 16;
+        let fraction = (exponent & 0xffff) as u128;
+        // A cubic approximation of 2^fraction, scaled by 2^16.
+        let factor = 65536 +
+                     ((195766423245049 * fraction + 971821376 * fraction * fraction +
+                       5127 * fraction * fraction * fraction + (1 << 47)) >> 48) as u64;
+
+        let anchor_target = compact_to_target(self.anchor_bits)?;
+        let target = match anchor_target.checked_mul(U256::from(factor)) {
+            Some(target) if shifts < 16 => target >> (16 - shifts).min(256) as u32,
+            Some(target) if shifts - 16 < 256 - target.bits() as i64 => {
+                target << (shifts - 16) as u32
+            }
+            _ => params.pow_limit,
+        };
+        if target.is_zero() {
+            return Ok(target_to_compact(U256::ONE));
+        }
+        Ok(target_to_compact(target.min(params.pow_limit)))
+    }
+}
+
+fn parent(history: &dyn HeaderHistory, height: u32) -> Result<&BlockHeader, BlockchainError> {
+    history
+        .header_at(height)
+        .ok_or(BlockchainError::OrphanBlock)
+}
+
+// target * numerator / denominator, capped at the proof-of-work limit.
+// Dividing first loses precision, so it's only done for targets so easy
+// that multiplying first would overflow.
+fn scale(target: U256, numerator: U256, denominator: U256, params: &ChainParams) -> U256 {
+    let target = match target.checked_mul(numerator) {
+        Some(product) => product / denominator,
+        None => {
+            (target / denominator)
+                .checked_mul(numerator)
+                .unwrap_or(params.pow_limit)
+        }
+    };
+    target.min(params.pow_limit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hash::Hash256;
+
+    // Headers at the given timestamps and bits.
+    struct Headers(Vec<BlockHeader>);
+
+    impl HeaderHistory for Headers {
+        fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+            self.0.get(height as usize)
+        }
+    }
+
+    fn headers(timestamps: &[u32], bits: u32) -> Headers {
+        Headers(timestamps
+                    .iter()
+                    .map(|timestamp| {
+                             BlockHeader::new(1,
+                                              Hash256::default(),
+                                              Hash256::default(),
+                                              *timestamp,
+                                              bits,
+                                              0)
+                         })
+                    .collect())
+    }
+
+    // Cases from Bitcoin Core's pow_tests.
+    #[test]
+    fn test_calculate_next_work_required() {
+        let params = ChainParams::mainnet();
+        assert_eq!(0x1d00d86a,
+                   calculate_next_work_required(0x1d00ffff, 1261130161, 1262152739, &params)
+                       .unwrap());
+        // Clamped to the proof-of-work limit.
+        assert_eq!(0x1d00ffff,
+                   calculate_next_work_required(0x1d00ffff, 1231006505, 1233061996, &params)
+                       .unwrap());
+        // The actual timespan is clamped to a quarter and four times the
+        // target.
+        assert_eq!(0x1c0168fd,
+                   calculate_next_work_required(0x1c05a3f4, 1279008237, 1279297671, &params)
+                       .unwrap());
+        assert_eq!(0x1d00e1fd,
+                   calculate_next_work_required(0x1c387f6f, 1263163443, 1269211443, &params)
+                       .unwrap());
+
+        assert_eq!(0x207fffff,
+                   calculate_next_work_required(0x207fffff, 0, 1, &ChainParams::regtest())
+                       .unwrap());
+    }
+
+    #[test]
+    fn test_min_difficulty_blocks() {
+        let params = ChainParams::testnet();
+        let pow_limit = target_to_compact(params.pow_limit);
+        let history = headers(&[1000, 2000], 0x1c05a3f4);
+        assert_eq!(0x1c05a3f4,
+                   Bitcoin.next_work_required(&history, 2, 3200, &params).unwrap());
+        assert_eq!(pow_limit,
+                   Bitcoin.next_work_required(&history, 2, 3201, &params).unwrap());
+
+        // Timestamps near the end of time don't overflow.
+        let history = headers(&[u32::MAX - 100, u32::MAX - 10], 0x1c05a3f4);
+        assert_eq!(0x1c05a3f4,
+                   Bitcoin.next_work_required(&history, 2, u32::MAX, &params).unwrap());
+    }
+
+    #[test]
+    fn test_retarget_equality() {
+        assert_eq!(ChainParams::mainnet(), ChainParams::mainnet());
+        assert_eq!(Retarget::Lwma(Lwma { window: 10 }), Retarget::Lwma(Lwma { window: 10 }));
+        assert!(Retarget::Lwma(Lwma { window: 10 }) != Retarget::Lwma(Lwma { window: 11 }));
+        assert!(Retarget::Bitcoin != Retarget::Lwma(Lwma { window: 10 }));
+
+        let custom: Arc<dyn RetargetAlgorithm> = Arc::new(Lwma { window: 10 });
+        assert_eq!(Retarget::Custom(custom.clone()), Retarget::Custom(custom.clone()));
+        assert!(Retarget::Custom(custom) != Retarget::Custom(Arc::new(Lwma { window: 10 })));
+    }
+
+    #[test]
+    fn test_lwma() {
+        const LWMA: Lwma = Lwma { window: 10 };
+        let params = ChainParams {
+            pow_target_spacing: 10,
+            pow_no_retargeting: false,
+            retarget: Retarget::Lwma(LWMA),
+            ..ChainParams::mainnet()
+        };
+        let on_time: Vec<u32> = (0..11).map(|height| 1000 + height * 10).collect();
+        let history = headers(&on_time, 0x1c05a3f4);
+        assert_eq!(0x1c05a3f4,
+                   LWMA.next_work_required(&history, 11, 1110, &params).unwrap());
+        assert_eq!(target_to_compact(params.pow_limit),
+                   LWMA.next_work_required(&history, 10, 1100, &params).unwrap());
+
+        // Blocks twice as fast as they should be halve the target.
+        let fast: Vec<u32> = (0..11).map(|height| 1000 + height * 5).collect();
+        let history = headers(&fast, 0x1c05a3f4);
+        assert_eq!(0x1c02d1fa,
+                   LWMA.next_work_required(&history, 11, 1055, &params).unwrap());
+    }
+
+    #[test]
+    fn test_asert() {
+        let asert = Asert {
+            anchor_height: 1,
+            anchor_bits: 0x1c05a3f4,
+            anchor_parent_timestamp: 1000000,
+            half_life: 2 * 24 * 60 * 60,
+        };
+        let params = ChainParams {
+            pow_no_retargeting: false,
+            ..ChainParams::mainnet()
+        };
+        let spacing = params.pow_target_spacing;
+
+        let history = headers(&[1000000, 1000000 + spacing], 0x1c05a3f4);
+        assert_eq!(0x1c05a3f4,
+                   asert.next_work_required(&history, 1, 0, &params).unwrap());
+        assert_eq!(0x1c05a3f4,
+                   asert.next_work_required(&history, 2, 0, &params).unwrap());
+
+        // One half-life behind schedule doubles the target, and one ahead
+        // halves it.
+        let history = headers(&[1000000, 1000000 + spacing + asert.half_life], 0x1c05a3f4);
+        assert_eq!(0x1c0b47e8,
+                   asert.next_work_required(&history, 2, 0, &params).unwrap());
+        let history = headers(&[1000000, 1000000 + spacing - asert.half_life], 0x1c05a3f4);
+        assert_eq!(0x1c02d1fa,
+                   asert.next_work_required(&history, 2, 0, &params).unwrap());
+    }
+}
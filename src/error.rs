@@ -0,0 +1,130 @@
+use core::fmt;
+use crate::hash::Hash256;
+use crate::io;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+#[derive(Debug)]
+pub enum BlockchainError {
+    Io(io::Error),
+    // The input ended in the middle of a value.
+    TruncatedInput,
+    // Bytes were left over after deserializing a value.
+    TrailingData(usize),
+    InvalidHex,
+    // A VarInt used a longer encoding than its value needs.
+    NonCanonicalVarInt,
+    // A length read from the input is larger than the deserialization limit.
+    LimitExceeded { what: &'static str, limit: usize, found: u64 },
+    InvalidLength { expected: usize, found: usize },
+    BadMagic { expected: u32, found: u32 },
+    // The compact target is negative, zero or overflows 256 bits.
+    InvalidTarget,
+    // The target is easier than the chain's proof-of-work limit.
+    TargetTooEasy,
+    // The header hash does not meet its target.
+    HashAboveTarget,
+    // The header's merkle root does not match the block's contents.
+    InvalidMerkleRoot,
+    InvalidAuxPow(&'static str),
+    InvalidWitness(&'static str),
+    InvalidScript(&'static str),
+    InvalidTransaction(&'static str),
+    // A signature hash can't be computed for the given input and type.
+    InvalidSighash(&'static str),
+    // A signature isn't encoded as the requested strictness level allows.
+    InvalidSignature(&'static str),
+    // The block is already in the chain.
+    DuplicateBlock,
+    // The block's previous block isn't in the chain.
+    OrphanBlock,
+    // The block doesn't fit onto the chain it's being added to.
+    InvalidBlock(&'static str),
+    // The block isn't in the chain.
+    UnknownBlock,
+    // Activating the block would disconnect more blocks than the chain's
+    // reorg limit allows.
+    ReorgTooDeep { depth: u32, limit: u32 },
+    // A stored block failed a check when the chain was verified.
+    CorruptBlock { height: u32, hash: Hash256, reason: &'static str },
+    // An amount is negative or outside the money range.
+    InvalidAmount,
+    // A JSON field is missing or has the wrong type or format.
+    InvalidJson(&'static str),
+}
+
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockchainError::Io(ref error) => write!(f, "I/O error: {}", error),
+            BlockchainError::TruncatedInput => write!(f, "unexpected end of input"),
+            BlockchainError::TrailingData(count) => {
+                write!(f, "{} unexpected trailing bytes", count)
+            }
+            BlockchainError::InvalidHex => write!(f, "invalid hex string"),
+            BlockchainError::NonCanonicalVarInt => write!(f, "non-canonical VarInt encoding"),
+            BlockchainError::LimitExceeded { what, limit, found } => {
+                write!(f, "{} of {} exceeds the limit of {}", what, found, limit)
+            }
+            BlockchainError::InvalidLength { expected, found } => {
+                write!(f, "invalid length: expected {}, found {}", expected, found)
+            }
+            BlockchainError::BadMagic { expected, found } => {
+                write!(f,
+                       "bad network magic: expected {:#010x}, found {:#010x}",
+                       expected,
+                       found)
+            }
+            BlockchainError::InvalidTarget => write!(f, "invalid compact target"),
+            BlockchainError::TargetTooEasy => write!(f, "target is above the proof-of-work limit"),
+            BlockchainError::HashAboveTarget => write!(f, "block hash does not meet its target"),
+            BlockchainError::InvalidMerkleRoot => write!(f, "merkle root does not match block"),
+            BlockchainError::InvalidWitness(reason) => write!(f, "invalid witness: {}", reason),
+            BlockchainError::InvalidAuxPow(reason) => {
+                write!(f, "invalid aux proof-of-work: {}", reason)
+            }
+            BlockchainError::InvalidScript(reason) => write!(f, "invalid script: {}", reason),
+            BlockchainError::InvalidAmount => write!(f, "amount out of range"),
+            BlockchainError::InvalidTransaction(reason) => {
+                write!(f, "invalid transaction: {}", reason)
+            }
+            BlockchainError::InvalidSighash(reason) => {
+                write!(f, "invalid signature hash: {}", reason)
+            }
+            BlockchainError::InvalidSignature(reason) => {
+                write!(f, "invalid signature: {}", reason)
+            }
+            BlockchainError::DuplicateBlock => write!(f, "block is already in the chain"),
+            BlockchainError::OrphanBlock => write!(f, "previous block is not in the chain"),
+            BlockchainError::InvalidBlock(reason) => write!(f, "invalid block: {}", reason),
+            BlockchainError::UnknownBlock => write!(f, "block is not in the chain"),
+            BlockchainError::ReorgTooDeep { depth, limit } => {
+                write!(f, "reorganization of {} blocks exceeds the limit of {}", depth, limit)
+            }
+            BlockchainError::CorruptBlock { height, hash, reason } => {
+                write!(f, "corrupt block {} at height {}: {}", hash, height, reason)
+            }
+            BlockchainError::InvalidJson(field) => write!(f, "invalid JSON field: {}", field),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for BlockchainError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BlockchainError::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BlockchainError {
+    fn from(error: io::Error) -> BlockchainError {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            BlockchainError::TruncatedInput
+        } else {
+            BlockchainError::Io(error)
+        }
+    }
+}
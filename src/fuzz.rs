@@ -0,0 +1,92 @@
+use crate::block::{Block, BlockHeader};
+use crate::script::{Instruction, Script};
+use crate::transaction::Transaction;
+use crate::util::*;
+
+// Entry points for cargo-fuzz targets. Each takes arbitrary bytes and
+// panics if it finds a bug, so a target can be as short as
+//
+//     fuzz_target!(|data: &[u8]| blockchain::fuzz::transaction_round_trip(data));
+//
+// Inputs that don't parse are fine; only inconsistencies panic.
+
+pub fn transaction_round_trip(data: &[u8]) {
+    round_trip::<Transaction>(data);
+}
+
+pub fn block_header_round_trip(data: &[u8]) {
+    round_trip::<BlockHeader>(data);
+}
+
+pub fn block_round_trip(data: &[u8]) {
+    round_trip::<Block<Transaction>>(data);
+}
+
+// Runs the script analysis functions over `data` as a raw script. There's
+// no interpreter, so these are what has to cope with hostile scripts.
+pub fn script_analysis(data: &[u8]) {
+    let script = Script::from(data.to_vec());
+    round_trip::<Script>(&script.serialize().unwrap());
+
+    let mut length = 0;
+    for instruction in script.instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(bytes)) => length += bytes.len() + 1,
+            Ok(Instruction::Op(_)) => length += 1,
+            Err(_) => break,
+        }
+    }
+    assert!(length <= data.len());
+
+    script.to_asm(true);
+    script.script_type().name();
+    script.is_push_only();
+    script.is_unspendable();
+    script.sigop_count(true);
+    script.sigop_count(false);
+    script.p2sh_sigop_count(&script);
+    assert!(script.without_code_separators().len() <= script.len());
+    script.witness_program();
+    let _ = script.checked_witness_program();
+    script.is_upgradable_witness_program();
+    if let Ok(redeem_script) = script.p2sh_redeem_script(&script) {
+        assert!(redeem_script.len() < script.len());
+    }
+}
+
+// A value that parses must serialize back to exactly the bytes it was read
+// from, and parse again to the same value.
+fn round_trip<T: Serializable + PartialEq + core::fmt::Debug>(data: &[u8]) {
+    let (value, consumed) = match T::deserialize_partial(data) {
+        Ok(parsed) => parsed,
+        Err(_) => return,
+    };
+    let bytes = value.serialize().unwrap();
+    assert_eq!(&data[..consumed], bytes.as_slice());
+    assert_eq!(bytes.len(), value.serialized_size());
+    assert_eq!(value, T::deserialize(&bytes).unwrap());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::genesis::genesis_block;
+    use crate::params::Network;
+
+    #[test]
+    fn test_fuzz_entry_points() {
+        let block = genesis_block(Network::Mainnet).serialize().unwrap();
+        block_round_trip(&block);
+        block_header_round_trip(&block[..80]);
+        transaction_round_trip(&block[81..]);
+        script_analysis(genesis_block(Network::Mainnet).data()[0].outputs()[0]
+                            .script()
+                            .as_bytes());
+
+        for data in &[&[][..], &[0xff; 10], &[0x00, 0x4c], &[0x4e, 0xff, 0xff, 0xff, 0xff]] {
+            block_round_trip(data);
+            transaction_round_trip(data);
+            script_analysis(data);
+        }
+    }
+}
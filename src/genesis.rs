@@ -0,0 +1,201 @@
+use crate::amount::Amount;
+use crate::block::{Block, BlockBuilder, compact_to_target, now};
+use crate::error::BlockchainError;
+use crate::hash::Hash256;
+use crate::locktime::{LockTime, Sequence};
+use crate::opcodes::{OP_CHECKSIG, OP_PUSHDATA1};
+use crate::params::Network;
+use crate::prelude::*;
+use crate::script::Script;
+use crate::transaction::{Input, Outpoint, Output, Transaction};
+use crate::util::*;
+
+const BITCOIN_MESSAGE: &str = "The Times 03/Jan/2009 Chancellor on brink of \
+                               second bailout for banks";
+const BITCOIN_KEY: &str = "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6\
+                           49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f";
+
+// The genesis block of one of the built-in networks. They share a coinbase
+// and differ in timestamp, bits and nonce.
+pub fn genesis_block(network: Network) -> Block<Transaction> {
+    let (timestamp, bits, nonce) = match network {
+        Network::Mainnet => (1231006505, 0x1d00ffff, 2083236893),
+        Network::Testnet => (1296688602, 0x1d00ffff, 414098458),
+        Network::Regtest => (1296688602, 0x207fffff, 2),
+    };
+    GenesisBuilder::new()
+        .timestamp(timestamp)
+        .bits(bits)
+        .nonce(nonce)
+        .build()
+        .unwrap()
+}
+
+// Builds and mines the genesis block of a private chain. The coinbase is
+// laid out like Bitcoin's, with the message in the scriptSig and the whole
+// reward paid to one script; the defaults reproduce Bitcoin's coinbase with
+// regtest's easy target.
+#[derive(Clone, Debug)]
+pub struct GenesisBuilder {
+    message: Vec<u8>,
+    script_pubkey: Script,
+    reward: Amount,
+    version: u32,
+    bits: u32,
+    timestamp: Option<u32>,
+    nonce: u32,
+}
+
+impl GenesisBuilder {
+    pub fn new() -> GenesisBuilder {
+        let mut script_pubkey = vec![65];
+        script_pubkey.extend_from_slice(&from_hex(BITCOIN_KEY).unwrap());
+        script_pubkey.push(OP_CHECKSIG);
+        GenesisBuilder {
+            message: BITCOIN_MESSAGE.as_bytes().to_vec(),
+            script_pubkey: Script::from(script_pubkey),
+            reward: Amount::from_sat(50 * Amount::ONE_BTC.to_sat()),
+            version: 1,
+            bits: 0x207fffff,
+            timestamp: None,
+            nonce: 0,
+        }
+    }
+
+    // At most 255 bytes.
+    pub fn message(mut self, message: &[u8]) -> GenesisBuilder {
+        self.message = message.to_vec();
+        self
+    }
+
+    pub fn script_pubkey(mut self, script_pubkey: Script) -> GenesisBuilder {
+        self.script_pubkey = script_pubkey;
+        self
+    }
+
+    pub fn reward(mut self, reward: Amount) -> GenesisBuilder {
+        self.reward = reward;
+        self
+    }
+
+    pub fn version(mut self, version: u32) -> GenesisBuilder {
+        self.version = version;
+        self
+    }
+
+    pub fn bits(mut self, bits: u32) -> GenesisBuilder {
+        self.bits = bits;
+        self
+    }
+
+    // Defaults to the current time, like BlockBuilder.
+    pub fn timestamp(mut self, timestamp: u32) -> GenesisBuilder {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    // The nonce to start mining from.
+    pub fn nonce(mut self, nonce: u32) -> GenesisBuilder {
+        self.nonce = nonce;
+        self
+    }
+
+    // Mines the block, moving the timestamp on by a second whenever the
+    // nonces run out. Bits that don't encode a valid target are an error.
+    pub fn build(self) -> Result<Block<Transaction>, BlockchainError> {
+        if self.message.len() > 0xff {
+            return Err(BlockchainError::LimitExceeded {
+                           what: "genesis message size",
+                           limit: 0xff,
+                           found: self.message.len() as u64,
+                       });
+        }
+        compact_to_target(self.bits)?;
+        // Bitcoin's scriptSig pushes the mainnet bits and the number 4
+        // before the message, whatever the block's own bits.
+        let mut script_sig = vec![0x04, 0xff, 0xff, 0x00, 0x1d, 0x01, 0x04];
+        if self.message.len() >= OP_PUSHDATA1 as usize {
+            script_sig.push(OP_PUSHDATA1);
+        }
+        script_sig.push(self.message.len() as u8);
+        script_sig.extend_from_slice(&self.message);
+        let coinbase = Transaction::new(1,
+                                        &[Input::new(Outpoint::null(),
+                                                     Script::from(script_sig),
+                                                     Sequence::MAX)],
+                                        &[Output::new(self.reward, self.script_pubkey)],
+                                        LockTime::ZERO);
+
+        let mut block = BlockBuilder::new()
+            .version(self.version)
+            .previous_hash(Hash256::default())
+            .bits(self.bits)
+            .timestamp(self.timestamp.unwrap_or_else(now))
+            .nonce(self.nonce)
+            .push(coinbase)
+            .build()?;
+        loop {
+            match block.header().check_own_target() {
+                Ok(()) => break,
+                Err(BlockchainError::HashAboveTarget) => {}
+                Err(error) => return Err(error),
+            }
+            let nonce = block.header().nonce().wrapping_add(1);
+            if nonce == 0 {
+                let timestamp = block.header()
+                    .timestamp()
+                    .checked_add(1)
+                    .ok_or(BlockchainError::InvalidBlock("no timestamps left to mine with"))?;
+                block.set_timestamp(timestamp);
+            }
+            block.set_nonce(nonce);
+        }
+
+        Ok(block)
+    }
+}
+
+impl Default for GenesisBuilder {
+    fn default() -> GenesisBuilder {
+        GenesisBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use crate::params::ChainParams;
+
+    #[test]
+    fn test_genesis_blocks() {
+        let hash = |network| genesis_block(network).header_hash().unwrap().to_string();
+        assert_eq!("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+                   hash(Network::Mainnet));
+        assert_eq!("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+                   hash(Network::Testnet));
+        assert_eq!("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                   hash(Network::Regtest));
+        assert_eq!("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                   genesis_block(Network::Mainnet).header().merkle_root_hash().to_string());
+    }
+
+    #[test]
+    fn test_genesis_builder() {
+        let genesis = GenesisBuilder::new()
+            .message(&[b'x'; 100])
+            .script_pubkey(Script::from(vec![0x51]))
+            .timestamp(1500000000)
+            .build()
+            .unwrap();
+        assert_eq!(&[OP_PUSHDATA1, 100],
+                   &genesis.data()[0].inputs()[0].script().as_bytes()[7..9]);
+        let chain = Blockchain::new(genesis, ChainParams::regtest()).unwrap();
+        assert_eq!(0, chain.height());
+
+        assert!(matches!(GenesisBuilder::new().message(&[0; 256]).build(),
+                         Err(BlockchainError::LimitExceeded { .. })));
+        assert!(matches!(GenesisBuilder::new().bits(0x01000000).timestamp(1).build(),
+                         Err(BlockchainError::InvalidTarget)));
+    }
+}
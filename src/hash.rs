@@ -0,0 +1,218 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::str::FromStr;
+use crate::error::BlockchainError;
+use crate::io::{Read, Write};
+use crate::util::*;
+use crate::prelude::*;
+use ring;
+use ripemd::{Digest, Ripemd160};
+
+// A 32-byte hash in internal byte order. Displayed and parsed in the
+// reversed-hex form used by RPC interfaces and block explorers.
+#[derive(Clone, Copy, Default)]
+pub struct Hash256([u8; 32]);
+
+impl Hash256 {
+    pub fn from_slice(bytes: &[u8]) -> Result<Hash256, BlockchainError> {
+        if bytes.len() != 32 {
+            return Err(BlockchainError::InvalidLength {
+                           expected: 32,
+                           found: bytes.len(),
+                       });
+        }
+        let mut hash = [0; 32];
+        hash.copy_from_slice(bytes);
+
+        Ok(Hash256(hash))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+}
+
+pub fn single_hash(data: &[u8]) -> Hash256 {
+    let digest = ring::digest::digest(&ring::digest::SHA256, data);
+    let mut hash = [0; 32];
+    hash.copy_from_slice(digest.as_ref());
+
+    Hash256(hash)
+}
+
+pub fn double_hash(data: &[u8]) -> Hash256 {
+    single_hash(single_hash(data).as_bytes())
+}
+
+// The name other Bitcoin libraries use for double_hash.
+pub fn sha256d(data: &[u8]) -> Hash256 {
+    double_hash(data)
+}
+
+// RIPEMD-160 of SHA-256, as committed to by addresses and P2SH and P2WPKH
+// outputs.
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    let mut hash = [0; 20];
+    hash.copy_from_slice(&Ripemd160::digest(single_hash(data).as_bytes()));
+    hash
+}
+
+// BIP340 tagged hash: SHA256(SHA256(tag) || SHA256(tag) || data).
+pub fn tagged_hash(tag: &str, data: &[u8]) -> Hash256 {
+    let tag_hash = single_hash(tag.as_bytes());
+    let mut buffer: Vec<u8> = Vec::with_capacity(64 + data.len());
+    buffer.extend_from_slice(tag_hash.as_bytes());
+    buffer.extend_from_slice(tag_hash.as_bytes());
+    buffer.extend_from_slice(data);
+    single_hash(&buffer)
+}
+
+impl PartialEq for Hash256 {
+    fn eq(&self, other: &Hash256) -> bool {
+        ring::constant_time::verify_slices_are_equal(&self.0, &other.0).is_ok()
+    }
+}
+
+impl Eq for Hash256 {}
+
+impl Hash for Hash256 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl PartialOrd for Hash256 {
+    fn partial_cmp(&self, other: &Hash256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Hash256 {
+    fn cmp(&self, other: &Hash256) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut reversed = self.0;
+        reversed.reverse();
+        write!(f, "{}", to_hex(&reversed))
+    }
+}
+
+impl fmt::Debug for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash256({})", self)
+    }
+}
+
+impl FromStr for Hash256 {
+    type Err = BlockchainError;
+
+    fn from_str(s: &str) -> Result<Hash256, BlockchainError> {
+        let mut bytes = from_hex(s)?;
+        bytes.reverse();
+        Hash256::from_slice(&bytes)
+    }
+}
+
+impl From<[u8; 32]> for Hash256 {
+    fn from(bytes: [u8; 32]) -> Hash256 {
+        Hash256(bytes)
+    }
+}
+
+impl From<Hash256> for [u8; 32] {
+    fn from(hash: Hash256) -> [u8; 32] {
+        hash.0
+    }
+}
+
+impl AsRef<[u8]> for Hash256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Hash256 {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Hash256 {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Hash256, D::Error> {
+        let hex = ::alloc::string::String::deserialize(deserializer)?;
+        hex.parse().map_err(::serde::de::Error::custom)
+    }
+}
+
+impl Serializable for Hash256 {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        writer.write_all(&self.0)?;
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        32
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        let mut hash = [0; 32];
+        reader.read_exact(&mut hash)?;
+
+        Ok(Hash256(hash))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_round_trip() {
+        // Hash of the mainnet genesis block.
+        let display = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let hash: Hash256 = display.parse().unwrap();
+        assert_eq!(0x6f, hash.as_bytes()[0]);
+        assert_eq!(0x00, hash.as_bytes()[31]);
+        assert_eq!(display, hash.to_string());
+    }
+
+    #[test]
+    fn test_from_str_errors() {
+        assert!(matches!("abcd".parse::<Hash256>(),
+                         Err(BlockchainError::InvalidLength { expected: 32, found: 2 })));
+        assert!(matches!("zz".parse::<Hash256>(), Err(BlockchainError::InvalidHex)));
+    }
+
+    #[test]
+    fn test_double_hash() {
+        assert_eq!("503d8319a48348cdc610a582f7bf754b5833df65038606eb48510790dfc99595",
+                   double_hash(b"hello").to_string());
+        assert_eq!(double_hash(b"hello"), sha256d(b"hello"));
+    }
+
+    #[test]
+    fn test_hash160() {
+        assert_eq!("b472a266d0bd89c13706a4132ccfb16f7c3b9fcb", to_hex(&hash160(b"")));
+        // The key hash of the address in the mainnet genesis coinbase.
+        let key = from_hex("04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb6\
+                            49f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f")
+                .unwrap();
+        assert_eq!("62e907b15cbf27d5425399ebf6f0fb50ebb88f18", to_hex(&hash160(&key)));
+    }
+}
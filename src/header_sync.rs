@@ -0,0 +1,350 @@
+use alloc::collections::BTreeMap;
+use crate::block::{BlockHeader, ChainWork, now};
+use crate::blockchain::{Blockchain, MAX_FUTURE_BLOCK_TIME, MEDIAN_TIME_SPAN, block_locator};
+use crate::difficulty::{HeaderHistory, RetargetAlgorithm};
+use crate::error::BlockchainError;
+use crate::hash::Hash256;
+use crate::params::ChainParams;
+use crate::prelude::*;
+use crate::util::*;
+
+struct HeaderEntry {
+    header: BlockHeader,
+    height: u32,
+    chain_work: ChainWork,
+}
+
+// Headers-first sync: headers are validated as they arrive, ahead of their
+// blocks, and the branch with the most work becomes the best header chain.
+// That chain is tracked apart from a Blockchain's active chain, which
+// follows it as the blocks it lists are downloaded and appended.
+pub struct HeaderSync {
+    params: ChainParams,
+    entries: BTreeMap<Hash256, HeaderEntry>,
+    // Header hashes on the best header chain by height.
+    best: Vec<Hash256>,
+    // The current time in seconds since the epoch, if there's a clock.
+    clock: Option<fn() -> u32>,
+}
+
+impl HeaderSync {
+    pub fn new(genesis: &BlockHeader, params: ChainParams) -> Result<HeaderSync, BlockchainError> {
+        genesis.validate_pow(&params)?;
+        let hash = genesis.hash()?;
+        let mut chain_work = ChainWork::new();
+        chain_work.add_header(genesis)?;
+
+        let mut entries = BTreeMap::new();
+        entries.insert(hash,
+                       HeaderEntry {
+                           header: genesis.clone(),
+                           height: 0,
+                           chain_work: chain_work,
+                       });
+        Ok(HeaderSync {
+               params: params,
+               entries: entries,
+               best: vec![hash],
+               clock: if cfg!(feature = "std") { Some(now) } else { None },
+           })
+    }
+
+    // Starts from the headers of the chain's active chain, which it has
+    // already validated.
+    pub fn from_chain<T>(chain: &Blockchain<T>) -> Result<HeaderSync, BlockchainError>
+        where T: Serializable + Clone
+    {
+        let mut sync = HeaderSync::new(chain.get_header_by_height(0).unwrap(),
+                                       chain.params().clone())?;
+        for height in 1..=chain.height() {
+            let header = chain.get_header_by_height(height).unwrap();
+            let hash = sync.insert(header.clone())?;
+            sync.best.push(hash);
+        }
+
+        Ok(sync)
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    // Replaces the clock that timestamps are checked against, as with
+    // Blockchain::set_clock.
+    pub fn set_clock(&mut self, clock: Option<fn() -> u32>) {
+        self.clock = clock;
+    }
+
+    // Validates and adds a batch of headers, such as the contents of a
+    // headers message, returning how many were new. Headers already known
+    // are skipped. Validation stops at the first invalid header, keeping
+    // the ones before it.
+    pub fn add_headers(&mut self, headers: &[BlockHeader]) -> Result<usize, BlockchainError> {
+        let mut added = 0;
+        for header in headers {
+            if self.add_header(header)? {
+                added += 1;
+            }
+        }
+
+        Ok(added)
+    }
+
+    pub fn best_hash(&self) -> Hash256 {
+        *self.best.last().unwrap()
+    }
+
+    pub fn best_height(&self) -> u32 {
+        self.best_entry().height
+    }
+
+    pub fn best_chain_work(&self) -> ChainWork {
+        self.best_entry().chain_work
+    }
+
+    pub fn get_header_by_hash(&self, hash: &Hash256) -> Option<&BlockHeader> {
+        self.entries.get(hash).map(|entry| &entry.header)
+    }
+
+    pub fn get_header_by_height(&self, height: u32) -> Option<&BlockHeader> {
+        self.best
+            .get(height as usize)
+            .and_then(|hash| self.get_header_by_hash(hash))
+    }
+
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    // The locator of the best header chain, for the next getheaders.
+    pub fn block_locator(&self) -> Vec<Hash256> {
+        block_locator(&self.best)
+    }
+
+    // The next blocks `chain` needs to follow the best header chain: up to
+    // `max` of the blocks on it past the point where the two diverge,
+    // lowest first, skipping ones `chain` already has.
+    pub fn blocks_to_download<T>(&self, chain: &Blockchain<T>, max: usize) -> Vec<Hash256>
+        where T: Serializable + Clone
+    {
+        let mut height = chain.height().min(self.best_height());
+        while height > 0 &&
+              chain.get_header_by_height(height) != self.get_header_by_height(height) {
+            height -= 1;
+        }
+        self.best[height as usize + 1..]
+            .iter()
+            .filter(|hash| !chain.contains(hash))
+            .take(max)
+            .copied()
+            .collect()
+    }
+
+    fn add_header(&mut self, header: &BlockHeader) -> Result<bool, BlockchainError> {
+        let hash = header.hash()?;
+        if self.entries.contains_key(&hash) {
+            return Ok(false);
+        }
+        let previous_hash = header.previous_hash();
+        if !self.entries.contains_key(previous_hash) {
+            return Err(BlockchainError::OrphanBlock);
+        }
+        header.validate_pow(&self.params)?;
+        if header.bits() != self.next_work_required(previous_hash, header.timestamp())? {
+            return Err(BlockchainError::InvalidBlock("incorrect proof-of-work target"));
+        }
+        if header.timestamp() <= self.median_time_past(previous_hash) {
+            return Err(BlockchainError::InvalidBlock("timestamp is not after the median time"));
+        }
+        match self.clock {
+            Some(clock) if header.timestamp() > clock().saturating_add(MAX_FUTURE_BLOCK_TIME) => {
+                return Err(BlockchainError::InvalidBlock("timestamp is too far in the future"));
+            }
+            _ => {}
+        }
+
+        self.insert(header.clone())?;
+        if self.entries[&hash].chain_work > self.best_chain_work() {
+            self.activate(hash);
+        }
+
+        Ok(true)
+    }
+
+    // Adds a header whose parent is known, without validating it.
+    fn insert(&mut self, header: BlockHeader) -> Result<Hash256, BlockchainError> {
+        let hash = header.hash()?;
+        let parent = &self.entries[header.previous_hash()];
+        let height = parent.height + 1;
+        let mut chain_work = parent.chain_work;
+        chain_work.add_header(&header)?;
+        self.entries
+            .insert(hash,
+                    HeaderEntry {
+                        header: header,
+                        height: height,
+                        chain_work: chain_work,
+                    });
+
+        Ok(hash)
+    }
+
+    fn next_work_required(&self,
+                          parent: &Hash256,
+                          timestamp: u32)
+                          -> Result<u32, BlockchainError> {
+        let branch = Branch {
+            sync: self,
+            tip: *parent,
+        };
+        self.params
+            .retarget
+            .next_work_required(&branch, self.entries[parent].height + 1, timestamp, &self.params)
+    }
+
+    fn median_time_past(&self, hash: &Hash256) -> u32 {
+        let mut timestamps: Vec<u32> = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut entry = &self.entries[hash];
+        loop {
+            timestamps.push(entry.header.timestamp());
+            if timestamps.len() == MEDIAN_TIME_SPAN || entry.height == 0 {
+                break;
+            }
+            entry = &self.entries[entry.header.previous_hash()];
+        }
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+
+    fn best_entry(&self) -> &HeaderEntry {
+        &self.entries[&self.best_hash()]
+    }
+
+    fn is_best(&self, hash: &Hash256) -> bool {
+        self.entries
+            .get(hash)
+            .and_then(|entry| self.best.get(entry.height as usize))
+            .is_some_and(|best| best == hash)
+    }
+
+    fn ancestor(&self, hash: &Hash256, height: u32) -> Option<&HeaderEntry> {
+        let mut entry = self.entries.get(hash)?;
+        if height > entry.height {
+            return None;
+        }
+        if self.is_best(hash) {
+            return self.entries.get(&self.best[height as usize]);
+        }
+        while entry.height > height {
+            entry = &self.entries[entry.header.previous_hash()];
+        }
+        Some(entry)
+    }
+
+    // Makes the branch ending at `hash` the best header chain.
+    fn activate(&mut self, hash: Hash256) {
+        let mut connected = Vec::new();
+        let mut current = hash;
+        while !self.is_best(&current) {
+            connected.push(current);
+            current = *self.entries[&current].header.previous_hash();
+        }
+        connected.reverse();
+
+        let fork_height = self.entries[&current].height as usize;
+        self.best.truncate(fork_height + 1);
+        self.best.extend_from_slice(&connected);
+    }
+}
+
+// The branch of the header tree ending at `tip`, as seen by retarget
+// algorithms.
+struct Branch<'a> {
+    sync: &'a HeaderSync,
+    tip: Hash256,
+}
+
+impl HeaderHistory for Branch<'_> {
+    fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        self.sync
+            .ancestor(&self.tip, height)
+            .map(|entry| &entry.header)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::genesis::genesis_block;
+    use crate::params::Network;
+    use crate::script::Script;
+    use crate::transaction::Transaction;
+
+    fn chain() -> Blockchain<Transaction> {
+        Blockchain::new(genesis_block(Network::Regtest), ChainParams::regtest()).unwrap()
+    }
+
+    fn remine(header: &mut BlockHeader) {
+        while header.validate_pow(&ChainParams::regtest()).is_err() {
+            let nonce = header.nonce().wrapping_add(1);
+            header.set_nonce(nonce);
+        }
+    }
+
+    #[test]
+    fn test_header_sync() {
+        let mut source = chain();
+        let hashes = source.generate_to_script(20, &Script::from(vec![0x51])).unwrap();
+        let headers: Vec<BlockHeader> = (1..21)
+            .map(|height| source.get_header_by_height(height).unwrap().clone())
+            .collect();
+
+        let mut chain = chain();
+        let mut sync = HeaderSync::from_chain(&chain).unwrap();
+        assert_eq!(10, sync.add_headers(&headers[..10]).unwrap());
+        assert_eq!(10, sync.add_headers(&headers).unwrap());
+        assert_eq!(20, sync.best_height());
+        assert_eq!(hashes[19], sync.best_hash());
+        assert_eq!(source.block_locator(), sync.block_locator());
+        assert_eq!(source.chain_work(), sync.best_chain_work());
+
+        assert_eq!(&hashes[..5], sync.blocks_to_download(&chain, 5).as_slice());
+        for hash in &hashes[..3] {
+            chain.append(source.get_block_by_hash(hash).unwrap().clone()).unwrap();
+        }
+        assert_eq!(&hashes[3..], sync.blocks_to_download(&chain, 100).as_slice());
+
+        // A copy of the full chain starts where it left off.
+        let resumed = HeaderSync::from_chain(&chain).unwrap();
+        assert_eq!(3, resumed.best_height());
+        assert_eq!(chain.block_locator(), resumed.block_locator());
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        let mut source = chain();
+        source.generate_to_script(12, &Script::from(vec![0x51])).unwrap();
+        let mut headers: Vec<BlockHeader> = (1..13)
+            .map(|height| source.get_header_by_height(height).unwrap().clone())
+            .collect();
+        let mut sync = HeaderSync::from_chain(&chain()).unwrap();
+
+        assert!(matches!(sync.add_headers(&headers[1..]), Err(BlockchainError::OrphanBlock)));
+
+        // The headers before an invalid one are kept.
+        let median = headers[5].timestamp();
+        headers[11].set_timestamp(median);
+        remine(&mut headers[11]);
+        assert!(matches!(sync.add_headers(&headers), Err(BlockchainError::InvalidBlock(_))));
+        assert_eq!(11, sync.best_height());
+
+        let mut header = headers[11].clone();
+        header.set_timestamp(source.tip().header().timestamp());
+        header.set_nonce(0);
+        while header.check_own_target().is_ok() {
+            header.set_nonce(header.nonce() + 1);
+        }
+        assert!(matches!(sync.add_headers(&[header]), Err(BlockchainError::HashAboveTarget)));
+        assert_eq!(11, sync.best_height());
+    }
+}
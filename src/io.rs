@@ -0,0 +1,213 @@
+// The parts of std::io that serialization uses. With the `std` feature
+// these are std::io and byteorder's extension traits; without it, a minimal
+// replacement implemented for byte slices and vectors.
+
+#[cfg(feature = "std")]
+pub use byteorder::{ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Read, Take, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::*;
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use byteorder::ByteOrder;
+    use core::{cmp, fmt};
+    use crate::prelude::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ErrorKind {
+        UnexpectedEof,
+        WriteZero,
+    }
+
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error { kind: kind }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.kind {
+                ErrorKind::UnexpectedEof => write!(f, "unexpected end of input"),
+                ErrorKind::WriteZero => write!(f, "failed to write whole buffer"),
+            }
+        }
+    }
+
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error> {
+            while !buf.is_empty() {
+                match self.read(buf)? {
+                    0 => return Err(ErrorKind::UnexpectedEof.into()),
+                    n => buf = &mut buf[n..],
+                }
+            }
+
+            Ok(())
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+            let mut chunk = [0; 256];
+            let mut total = 0;
+            loop {
+                match self.read(&mut chunk)? {
+                    0 => return Ok(total),
+                    n => {
+                        buf.extend_from_slice(&chunk[..n]);
+                        total += n;
+                    }
+                }
+            }
+        }
+
+        fn take(self, limit: u64) -> Take<Self>
+            where Self: Sized
+        {
+            Take {
+                inner: self,
+                limit: limit,
+            }
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let size = cmp::min(buf.len(), self.len());
+            let (head, tail) = self.split_at(size);
+            buf[..size].copy_from_slice(head);
+            *self = tail;
+
+            Ok(size)
+        }
+    }
+
+    // Reads at most `limit` bytes from the inner reader.
+    pub struct Take<R> {
+        inner: R,
+        limit: u64,
+    }
+
+    impl<R> Take<R> {
+        // The number of bytes that can still be read.
+        pub fn limit(&self) -> u64 {
+            self.limit
+        }
+    }
+
+    impl<R: Read> Read for Take<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let size = cmp::min(buf.len() as u64, self.limit) as usize;
+            let read = self.inner.read(&mut buf[..size])?;
+            self.limit -= read as u64;
+
+            Ok(read)
+        }
+    }
+
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error>;
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+            while !buf.is_empty() {
+                match self.write(buf)? {
+                    0 => return Err(ErrorKind::WriteZero.into()),
+                    n => buf = &buf[n..],
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            (**self).write(buf)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.extend_from_slice(buf);
+
+            Ok(buf.len())
+        }
+    }
+
+    // byteorder's ReadBytesExt and WriteBytesExt, for the integer sizes the
+    // wire formats use.
+    pub trait ReadBytesExt: Read {
+        fn read_u8(&mut self) -> Result<u8, Error> {
+            let mut buf = [0; 1];
+            self.read_exact(&mut buf)?;
+            Ok(buf[0])
+        }
+
+        fn read_u16<T: ByteOrder>(&mut self) -> Result<u16, Error> {
+            let mut buf = [0; 2];
+            self.read_exact(&mut buf)?;
+            Ok(T::read_u16(&buf))
+        }
+
+        fn read_u32<T: ByteOrder>(&mut self) -> Result<u32, Error> {
+            let mut buf = [0; 4];
+            self.read_exact(&mut buf)?;
+            Ok(T::read_u32(&buf))
+        }
+
+        fn read_u64<T: ByteOrder>(&mut self) -> Result<u64, Error> {
+            let mut buf = [0; 8];
+            self.read_exact(&mut buf)?;
+            Ok(T::read_u64(&buf))
+        }
+    }
+
+    impl<R: Read + ?Sized> ReadBytesExt for R {}
+
+    pub trait WriteBytesExt: Write {
+        fn write_u8(&mut self, value: u8) -> Result<(), Error> {
+            self.write_all(&[value])
+        }
+
+        fn write_u16<T: ByteOrder>(&mut self, value: u16) -> Result<(), Error> {
+            let mut buf = [0; 2];
+            T::write_u16(&mut buf, value);
+            self.write_all(&buf)
+        }
+
+        fn write_u32<T: ByteOrder>(&mut self, value: u32) -> Result<(), Error> {
+            let mut buf = [0; 4];
+            T::write_u32(&mut buf, value);
+            self.write_all(&buf)
+        }
+
+        fn write_u64<T: ByteOrder>(&mut self, value: u64) -> Result<(), Error> {
+            let mut buf = [0; 8];
+            T::write_u64(&mut buf, value);
+            self.write_all(&buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> WriteBytesExt for W {}
+}
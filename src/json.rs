@@ -0,0 +1,359 @@
+use crate::amount::Amount;
+use crate::block::{Block, BlockHeader};
+use crate::error::BlockchainError;
+use crate::hash::Hash256;
+use crate::locktime::{LockTime, Sequence};
+use crate::script::Script;
+use crate::transaction::{Input, Outpoint, Output, Transaction, Witness};
+use crate::util::*;
+use serde_json::{Map, Value, json};
+
+// Conversions to and from the JSON that Bitcoin Core returns from
+// decoderawtransaction and getblock (at verbosity 2). Fields that need chain
+// state, like confirmations and height, are left out.
+
+pub fn transaction_to_json(transaction: &Transaction) -> Result<Value, BlockchainError> {
+    let is_coinbase = transaction.inputs().len() == 1 &&
+                      transaction.inputs()[0].previous_output().is_null();
+
+    let mut inputs: Vec<Value> = Vec::new();
+    for input in transaction.inputs() {
+        let mut object = Map::new();
+        if is_coinbase {
+            object.insert("coinbase".to_string(),
+                          Value::from(to_hex(input.script().as_bytes())));
+        } else {
+            object.insert("txid".to_string(),
+                          Value::from(input.previous_output().txid().to_string()));
+            object.insert("vout".to_string(),
+                          Value::from(input.previous_output().vout()));
+            object.insert("scriptSig".to_string(),
+                          json!({
+                                    "asm": input.script().to_asm(true),
+                                    "hex": to_hex(input.script().as_bytes()),
+                                }));
+        }
+        if !input.witness().is_empty() {
+            let items: Vec<String> = input.witness().items().iter().map(|i| to_hex(i)).collect();
+            object.insert("txinwitness".to_string(), Value::from(items));
+        }
+        object.insert("sequence".to_string(),
+                      Value::from(input.sequence().to_u32()));
+        inputs.push(Value::Object(object));
+    }
+
+    let outputs: Vec<Value> = transaction
+        .outputs()
+        .iter()
+        .enumerate()
+        .map(|(n, output)| {
+                 json!({
+                           "value": output.value().to_btc(),
+                           "n": n,
+                           "scriptPubKey": {
+                               "asm": output.script().to_asm(false),
+                               "hex": to_hex(output.script().as_bytes()),
+                               "type": output.script().script_type().name(),
+                           },
+                       })
+             })
+        .collect();
+
+    Ok(json!({
+                 "txid": transaction.txid()?.to_string(),
+                 "hash": transaction.wtxid()?.to_string(),
+                 "version": transaction.version(),
+                 "size": transaction.serialized_size(),
+                 "vsize": transaction.vsize(),
+                 "weight": transaction.weight(),
+                 "locktime": transaction.lock_time().to_consensus_u32(),
+                 "vin": inputs,
+                 "vout": outputs,
+             }))
+}
+
+pub fn transaction_from_json(value: &Value) -> Result<Transaction, BlockchainError> {
+    let mut inputs: Vec<Input> = Vec::new();
+    for input in array_field(value, "vin")? {
+        let (outpoint, script) = match input.get("coinbase") {
+            Some(coinbase) => (Outpoint::null(), hex_value(coinbase, "coinbase")?),
+            None => {
+                let txid = hash_field(input, "txid")?;
+                let vout = u32_field(input, "vout")?;
+                let script_sig = field(input, "scriptSig")?;
+                (Outpoint::new(txid, vout), hex_value(field(script_sig, "hex")?, "scriptSig")?)
+            }
+        };
+        let sequence = Sequence(u32_field(input, "sequence")?);
+        let mut items: Vec<Vec<u8>> = Vec::new();
+        if let Some(witness) = input.get("txinwitness") {
+            let witness = witness.as_array().ok_or(BlockchainError::InvalidJson("txinwitness"))?;
+            for item in witness {
+                items.push(hex_value(item, "txinwitness")?);
+            }
+        }
+        inputs.push(Input::new(outpoint, Script::from(script), sequence)
+                        .with_witness(Witness::new(items)));
+    }
+
+    let mut outputs: Vec<Output> = Vec::new();
+    for output in array_field(value, "vout")? {
+        let value = field(output, "value")?
+            .as_f64()
+            .and_then(|btc| Amount::from_btc(btc).ok())
+            .ok_or(BlockchainError::InvalidJson("value"))?;
+        let script = hex_value(field(field(output, "scriptPubKey")?, "hex")?, "scriptPubKey")?;
+        outputs.push(Output::new(value, Script::from(script)));
+    }
+
+    Ok(Transaction::new(u32_field(value, "version")?,
+                        &inputs,
+                        &outputs,
+                        LockTime::from_consensus(u32_field(value, "locktime")?)))
+}
+
+// The equivalents of Bitcoin Core's decoderawtransaction and decodescript,
+// minus the addresses.
+pub fn decode_raw_transaction(hex: &str) -> Result<Value, BlockchainError> {
+    transaction_to_json(&Transaction::from_hex(hex)?)
+}
+
+pub fn decode_script(hex: &str) -> Result<Value, BlockchainError> {
+    let script = Script::from(from_hex(hex.trim())?);
+    Ok(json!({
+                 "asm": script.to_asm(false),
+                 "type": script.script_type().name(),
+             }))
+}
+
+pub fn block_to_json(block: &Block<Transaction>) -> Result<Value, BlockchainError> {
+    let header = block.header();
+    let mut transactions: Vec<Value> = Vec::new();
+    let mut stripped_size = block.serialized_size();
+    for transaction in block.data() {
+        let mut json = transaction_to_json(transaction)?;
+        json["hex"] = Value::from(to_hex(&transaction.serialize()?));
+        transactions.push(json);
+        stripped_size -= transaction.serialized_size() - transaction.stripped_size();
+    }
+
+    let mut json = json!({
+                             "hash": header.hash()?.to_string(),
+                             "size": block.serialized_size(),
+                             "strippedsize": stripped_size,
+                             "weight": stripped_size * 3 + block.serialized_size(),
+                             "version": header.version(),
+                             "versionHex": format!("{:08x}", header.version()),
+                             "merkleroot": header.merkle_root_hash().to_string(),
+                             "tx": transactions,
+                             "time": header.timestamp(),
+                             "nonce": header.nonce(),
+                             "bits": format!("{:08x}", header.bits()),
+                             "difficulty": difficulty(header.bits()),
+                             "nTx": block.data().len(),
+                         });
+    // The genesis block has no previous block, so Bitcoin Core omits it.
+    if !header.previous_hash().is_zero() {
+        json["previousblockhash"] = Value::from(header.previous_hash().to_string());
+    }
+
+    Ok(json)
+}
+
+pub fn block_from_json(value: &Value) -> Result<Block<Transaction>, BlockchainError> {
+    let previous_hash = match value.get("previousblockhash") {
+        Some(_) => hash_field(value, "previousblockhash")?,
+        None => Hash256::default(),
+    };
+    let bits = u32::from_str_radix(str_field(value, "bits")?, 16)
+        .map_err(|_| BlockchainError::InvalidJson("bits"))?;
+    let header = BlockHeader::new(u32_field(value, "version")?,
+                                  previous_hash,
+                                  hash_field(value, "merkleroot")?,
+                                  u32_field(value, "time")?,
+                                  bits,
+                                  u32_field(value, "nonce")?);
+
+    let mut transactions: Vec<Transaction> = Vec::new();
+    for transaction in array_field(value, "tx")? {
+        transactions.push(transaction_from_json(transaction)?);
+    }
+
+    Ok(Block::from_parts(header, transactions))
+}
+
+// The target of `bits` as a multiple of the easiest mainnet target, computed
+// the same way as Bitcoin Core's GetDifficulty.
+fn difficulty(bits: u32) -> f64 {
+    let mut shift = (bits >> 24) & 0xff;
+    let mut difficulty = 0x0000ffff as f64 / (bits & 0x00ffffff) as f64;
+    while shift < 29 {
+        difficulty *= 256.0;
+        shift += 1;
+    }
+    while shift > 29 {
+        difficulty /= 256.0;
+        shift -= 1;
+    }
+    difficulty
+}
+
+fn field<'a>(value: &'a Value, name: &'static str) -> Result<&'a Value, BlockchainError> {
+    value.get(name).ok_or(BlockchainError::InvalidJson(name))
+}
+
+fn str_field<'a>(value: &'a Value, name: &'static str) -> Result<&'a str, BlockchainError> {
+    field(value, name)?
+        .as_str()
+        .ok_or(BlockchainError::InvalidJson(name))
+}
+
+fn u32_field(value: &Value, name: &'static str) -> Result<u32, BlockchainError> {
+    match field(value, name)?.as_u64() {
+        Some(number) if number <= u32::MAX as u64 => Ok(number as u32),
+        _ => Err(BlockchainError::InvalidJson(name)),
+    }
+}
+
+fn array_field<'a>(value: &'a Value, name: &'static str) -> Result<&'a [Value], BlockchainError> {
+    field(value, name)?
+        .as_array()
+        .map(|array| array.as_slice())
+        .ok_or(BlockchainError::InvalidJson(name))
+}
+
+fn hash_field(value: &Value, name: &'static str) -> Result<Hash256, BlockchainError> {
+    str_field(value, name)?
+        .parse()
+        .map_err(|_| BlockchainError::InvalidJson(name))
+}
+
+fn hex_value(value: &Value, name: &'static str) -> Result<Vec<u8>, BlockchainError> {
+    let hex = value.as_str().ok_or(BlockchainError::InvalidJson(name))?;
+    from_hex(hex).map_err(|_| BlockchainError::InvalidJson(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::{Block, BlockHeader};
+    use crate::transaction::Transaction;
+    use crate::util::Serializable;
+
+    // Signed native P2WPKH example from BIP143.
+    const SEGWIT_TRANSACTION: &str =
+        "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000\
+         494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040\
+         529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc\
+         89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb2060000\
+         00001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde\
+         42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4\
+         542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c21\
+         2a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee\
+         635711000000";
+
+    const GENESIS_COINBASE: &str =
+        "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d\
+         04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f\
+         6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2\
+         052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649\
+         f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+    #[test]
+    fn test_transaction_json() {
+        let transaction = Transaction::from_hex(SEGWIT_TRANSACTION).unwrap();
+        let json = transaction_to_json(&transaction).unwrap();
+
+        assert_eq!("e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609",
+                   json["txid"]);
+        assert_eq!("c36c38370907df2324d9ce9d149d191192f338b37665a82e78e76a12c909b762",
+                   json["hash"]);
+        assert_eq!(343, json["size"]);
+        assert_eq!(261, json["vsize"]);
+        assert_eq!(1042, json["weight"]);
+        assert_eq!(17, json["locktime"]);
+
+        let input = &json["vin"][0];
+        assert_eq!("9f96ade4b41d5433f4eda31e1738ec2b36f6e7d1420d94a6af99801a88f7f7ff",
+                   input["txid"]);
+        assert_eq!(0, input["vout"]);
+        assert_eq!("30450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be\
+                    022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed[ALL]",
+                   input["scriptSig"]["asm"]);
+        assert_eq!(4294967278u32, input["sequence"]);
+        assert!(input.get("txinwitness").is_none());
+        assert_eq!(2, json["vin"][1]["txinwitness"].as_array().unwrap().len());
+
+        let output = &json["vout"][0];
+        assert_eq!(1.1234, output["value"]);
+        assert_eq!(0, output["n"]);
+        assert_eq!("OP_DUP OP_HASH160 8280b37df378db99f66f85c95a783a76ac7a6d59 OP_EQUALVERIFY \
+                    OP_CHECKSIG",
+                   output["scriptPubKey"]["asm"]);
+        assert_eq!("pubkeyhash", output["scriptPubKey"]["type"]);
+
+        assert_eq!(transaction, transaction_from_json(&json).unwrap());
+    }
+
+    #[test]
+    fn test_genesis_block_json() {
+        let coinbase = Transaction::from_hex(GENESIS_COINBASE).unwrap();
+        let header = BlockHeader::new(1,
+                                      Hash256::default(),
+                                      coinbase.txid().unwrap(),
+                                      1231006505,
+                                      0x1d00ffff,
+                                      2083236893);
+        let block = Block::from_parts(header, vec![coinbase]);
+        let json = block_to_json(&block).unwrap();
+
+        assert_eq!("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+                   json["hash"]);
+        assert_eq!(285, json["size"]);
+        assert_eq!(285, json["strippedsize"]);
+        assert_eq!(1140, json["weight"]);
+        assert_eq!("00000001", json["versionHex"]);
+        assert_eq!("1d00ffff", json["bits"]);
+        assert_eq!(1.0, json["difficulty"]);
+        assert_eq!(1, json["nTx"]);
+        assert!(json.get("previousblockhash").is_none());
+
+        let coinbase = &json["tx"][0];
+        assert_eq!(GENESIS_COINBASE, coinbase["hex"]);
+        assert!(coinbase["vin"][0]["coinbase"].as_str().unwrap().starts_with("04ffff001d"));
+        assert_eq!(50.0, coinbase["vout"][0]["value"]);
+        assert_eq!("pubkey", coinbase["vout"][0]["scriptPubKey"]["type"]);
+
+        assert_eq!(block, block_from_json(&json).unwrap());
+    }
+
+    #[test]
+    fn test_decode() {
+        let json = decode_raw_transaction(SEGWIT_TRANSACTION).unwrap();
+        assert_eq!("e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609",
+                   json["txid"]);
+        assert!(decode_raw_transaction("0100").is_err());
+
+        let json = decode_script("76a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac").unwrap();
+        assert_eq!("OP_DUP OP_HASH160 8280b37df378db99f66f85c95a783a76ac7a6d59 OP_EQUALVERIFY \
+                    OP_CHECKSIG",
+                   json["asm"]);
+        assert_eq!("pubkeyhash", json["type"]);
+        assert_eq!("nonstandard", decode_script("").unwrap()["type"]);
+        assert!(matches!(decode_script("7"), Err(BlockchainError::InvalidHex)));
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let transaction = Transaction::from_hex(SEGWIT_TRANSACTION).unwrap();
+        let mut json = transaction_to_json(&transaction).unwrap();
+        json["vout"][1]["scriptPubKey"]["hex"] = Value::from("zz");
+        assert!(matches!(transaction_from_json(&json),
+                         Err(BlockchainError::InvalidJson("scriptPubKey"))));
+
+        json["vout"][1]["scriptPubKey"]["hex"] = Value::from("");
+        json.as_object_mut().unwrap().remove("locktime");
+        assert!(matches!(transaction_from_json(&json),
+                         Err(BlockchainError::InvalidJson("locktime"))));
+    }
+}
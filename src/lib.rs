@@ -1,9 +1,72 @@
-#![feature(box_syntax)]
+// Struct literals spell out `field: field` throughout.
+#![allow(clippy::redundant_field_names)]
+// Without the `std` feature only `core` and `alloc` are used, so headers,
+// transactions and merkle proofs can be handled on embedded targets.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
 extern crate byteorder;
+#[cfg(any(feature = "std", test))]
+extern crate core;
 extern crate ring;
+extern crate ripemd;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(any(test, feature = "json"))]
+extern crate serde_json;
+#[cfg(feature = "std")]
 extern crate time;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
-mod block;
-mod transaction;
-mod util;
+pub mod amount;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod auxpow;
+pub mod block;
+pub mod blockchain;
+pub mod difficulty;
+pub mod error;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod genesis;
+pub mod hash;
+pub mod header_sync;
+pub mod io;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod locktime;
+pub mod opcodes;
+pub mod orphan;
+pub mod params;
+pub mod script;
+pub mod sighash;
+pub mod signature;
+pub mod transaction;
+pub mod uint;
+pub mod util;
+
+// The names the std prelude would otherwise provide.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+}
+
+pub use amount::Amount;
+pub use block::{Block, BlockBuilder, BlockHeader, ChainWork};
+pub use blockchain::{Blockchain, ChainEvent, ChainListener, ChainUpdate};
+pub use error::BlockchainError;
+pub use genesis::{GenesisBuilder, genesis_block};
+pub use hash::Hash256;
+pub use header_sync::HeaderSync;
+pub use locktime::{LockTime, Sequence};
+pub use orphan::OrphanPool;
+pub use params::{ChainParams, Network};
+pub use script::Script;
+pub use sighash::SighashCache;
+pub use signature::Signature;
+pub use transaction::{Input, Outpoint, Output, Transaction, TransactionBuilder, Witness};
+pub use uint::U256;
+pub use util::{DeserializeLimits, Serializable};
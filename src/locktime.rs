@@ -0,0 +1,228 @@
+use byteorder::LittleEndian;
+use crate::error::BlockchainError;
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::util::*;
+
+// Lock times below this are block heights, at or above it unix timestamps.
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_VALUE_MASK: u32 = 0x0000ffff;
+
+// Relative time locks are expressed in units of 512 seconds.
+pub const SEQUENCE_GRANULARITY: u32 = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Sequence(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelativeLock {
+    Blocks(u16),
+    Time(u16),
+}
+
+impl Sequence {
+    pub const MAX: Sequence = Sequence(0xffffffff);
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xfffffffe);
+    pub const ENABLE_RBF_NO_LOCKTIME: Sequence = Sequence(0xfffffffd);
+
+    pub fn from_height(blocks: u16) -> Sequence {
+        Sequence(blocks as u32)
+    }
+
+    pub fn from_512_second_intervals(intervals: u16) -> Sequence {
+        Sequence(SEQUENCE_TYPE_FLAG | intervals as u32)
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_final(&self) -> bool {
+        *self == Sequence::MAX
+    }
+
+    // BIP125: any input with a sequence below 0xfffffffe opts in to
+    // replace-by-fee.
+    pub fn signals_rbf(&self) -> bool {
+        *self < Sequence::ENABLE_LOCKTIME_NO_RBF
+    }
+
+    // BIP68 relative lock, or None if the disable flag is set.
+    pub fn relative_lock(&self) -> Option<RelativeLock> {
+        if self.0 & SEQUENCE_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = (self.0 & SEQUENCE_VALUE_MASK) as u16;
+        if self.0 & SEQUENCE_TYPE_FLAG != 0 {
+            Some(RelativeLock::Time(value))
+        } else {
+            Some(RelativeLock::Blocks(value))
+        }
+    }
+}
+
+impl RelativeLock {
+    // Whether the lock has expired given the number of blocks and seconds
+    // that have passed since the spent output was confirmed.
+    pub fn is_satisfied_by(&self, blocks_elapsed: u32, seconds_elapsed: u32) -> bool {
+        match *self {
+            RelativeLock::Blocks(blocks) => blocks_elapsed >= blocks as u32,
+            RelativeLock::Time(intervals) => {
+                seconds_elapsed >= (intervals as u32) << SEQUENCE_GRANULARITY
+            }
+        }
+    }
+}
+
+impl Serializable for Sequence {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        writer.write_u32::<LittleEndian>(self.0)?;
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        4
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Ok(Sequence(reader.read_u32::<LittleEndian>()?))
+    }
+}
+
+// A transaction's absolute lock time, as its consensus value: a block
+// height below LOCK_TIME_THRESHOLD and a unix timestamp from it on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "u32", into = "u32"))]
+pub struct LockTime(u32);
+
+impl LockTime {
+    pub const ZERO: LockTime = LockTime(0);
+
+    pub fn from_consensus(value: u32) -> LockTime {
+        LockTime(value)
+    }
+
+    pub fn from_height(height: u32) -> Result<LockTime, BlockchainError> {
+        if height >= LOCK_TIME_THRESHOLD {
+            return Err(BlockchainError::InvalidTransaction("lock time height out of range"));
+        }
+        Ok(LockTime(height))
+    }
+
+    pub fn from_time(time: u32) -> Result<LockTime, BlockchainError> {
+        if time < LOCK_TIME_THRESHOLD {
+            return Err(BlockchainError::InvalidTransaction("lock time is not a timestamp"));
+        }
+        Ok(LockTime(time))
+    }
+
+    pub fn to_consensus_u32(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_block_height(&self) -> bool {
+        self.0 < LOCK_TIME_THRESHOLD
+    }
+
+    pub fn is_time(&self) -> bool {
+        !self.is_block_height()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    // Whether a transaction with this lock time may be included in a block at
+    // the given height and time.
+    pub fn is_satisfied_by(&self, height: u32, time: u32) -> bool {
+        if self.is_zero() {
+            return true;
+        }
+        if self.is_block_height() {
+            self.0 < height
+        } else {
+            self.0 < time
+        }
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(value: u32) -> LockTime {
+        LockTime::from_consensus(value)
+    }
+}
+
+impl From<LockTime> for u32 {
+    fn from(lock_time: LockTime) -> u32 {
+        lock_time.to_consensus_u32()
+    }
+}
+
+impl Serializable for LockTime {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        writer.write_u32::<LittleEndian>(self.to_consensus_u32())?;
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        4
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Ok(LockTime::from_consensus(reader.read_u32::<LittleEndian>()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence() {
+        assert!(Sequence::MAX.is_final());
+        assert!(!Sequence::MAX.signals_rbf());
+        assert!(!Sequence::ENABLE_LOCKTIME_NO_RBF.signals_rbf());
+        assert!(Sequence::ENABLE_RBF_NO_LOCKTIME.signals_rbf());
+
+        assert_eq!(None, Sequence::MAX.relative_lock());
+        assert_eq!(Some(RelativeLock::Blocks(10)),
+                   Sequence::from_height(10).relative_lock());
+        assert_eq!(Some(RelativeLock::Time(3)),
+                   Sequence::from_512_second_intervals(3).relative_lock());
+
+        assert!(RelativeLock::Blocks(10).is_satisfied_by(10, 0));
+        assert!(!RelativeLock::Blocks(10).is_satisfied_by(9, 100000));
+        assert!(RelativeLock::Time(3).is_satisfied_by(0, 1536));
+        assert!(!RelativeLock::Time(3).is_satisfied_by(100, 1535));
+    }
+
+    #[test]
+    fn test_lock_time() {
+        let height = LockTime::from_consensus(499999999);
+        assert!(height.is_block_height());
+        assert!(!height.is_time());
+        assert_eq!(height, LockTime::from_height(499999999).unwrap());
+        let time = LockTime::from_consensus(500000000);
+        assert!(time.is_time());
+        assert_eq!(time, LockTime::from_time(500000000).unwrap());
+        assert!(LockTime::from_height(500000000).is_err());
+        assert!(LockTime::from_time(499999999).is_err());
+
+        let height = |height| LockTime::from_height(height).unwrap();
+        let time = |time| LockTime::from_time(time).unwrap();
+        assert!(LockTime::ZERO.is_satisfied_by(0, 0));
+        assert!(height(100).is_satisfied_by(101, 0));
+        assert!(!height(100).is_satisfied_by(100, 2000000000));
+        assert!(time(1500000000).is_satisfied_by(0, 1500000001));
+        assert!(!time(1500000000).is_satisfied_by(1000000, 1500000000));
+
+        let serialized = time(1500000000).serialize().unwrap();
+        assert_eq!(time(1500000000), LockTime::deserialize(&serialized).unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+use alloc::collections::BTreeMap;
+use crate::block::Block;
+use crate::error::BlockchainError;
+use crate::hash::Hash256;
+use crate::prelude::*;
+use crate::util::*;
+
+pub const DEFAULT_MAX_ORPHANS: usize = 100;
+pub const DEFAULT_ORPHAN_EXPIRY: u32 = 20 * 60;
+
+struct Orphan<T: Serializable + Clone> {
+    block: Block<T>,
+    // When the block arrived, in seconds since the epoch.
+    received: u32,
+}
+
+// Blocks whose previous block isn't known yet, held until it arrives. The
+// pool is bounded: past `max_orphans` the oldest block is dropped, and
+// blocks older than `expiry` seconds are dropped by expire().
+pub struct OrphanPool<T: Serializable + Clone> {
+    orphans: BTreeMap<Hash256, Orphan<T>>,
+    // Orphan hashes by the hash of the block they're waiting for.
+    by_parent: BTreeMap<Hash256, Vec<Hash256>>,
+    max_orphans: usize,
+    expiry: u32,
+}
+
+impl<T: Serializable + Clone> OrphanPool<T> {
+    pub fn new(max_orphans: usize, expiry: u32) -> OrphanPool<T> {
+        OrphanPool {
+            orphans: BTreeMap::new(),
+            by_parent: BTreeMap::new(),
+            max_orphans: max_orphans,
+            expiry: expiry,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.orphans.contains_key(hash)
+    }
+
+    // Adds the block, evicting the oldest orphan if the pool is full.
+    // Returns false if the block was already in the pool.
+    pub fn insert(&mut self, block: Block<T>, received: u32) -> Result<bool, BlockchainError> {
+        let hash = block.header_hash()?;
+        if self.orphans.contains_key(&hash) || self.max_orphans == 0 {
+            return Ok(false);
+        }
+        if self.orphans.len() >= self.max_orphans {
+            let oldest = self.orphans
+                .iter()
+                .min_by_key(|(_, orphan)| orphan.received)
+                .map(|(hash, _)| *hash)
+                .unwrap();
+            self.remove(&oldest);
+        }
+
+        self.by_parent
+            .entry(*block.header().previous_hash())
+            .or_default()
+            .push(hash);
+        self.orphans
+            .insert(hash,
+                    Orphan {
+                        block: block,
+                        received: received,
+                    });
+
+        Ok(true)
+    }
+
+    // Removes and returns the orphans waiting for `parent`.
+    pub fn take_children(&mut self, parent: &Hash256) -> Vec<Block<T>> {
+        self.by_parent
+            .remove(parent)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|hash| self.orphans.remove(hash))
+            .map(|orphan| orphan.block)
+            .collect()
+    }
+
+    // Drops orphans that arrived more than `expiry` seconds before `now`.
+    pub fn expire(&mut self, now: u32) {
+        let expired: Vec<Hash256> = self.orphans
+            .iter()
+            .filter(|(_, orphan)| now.saturating_sub(orphan.received) > self.expiry)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired {
+            self.remove(&hash);
+        }
+    }
+
+    fn remove(&mut self, hash: &Hash256) {
+        let orphan = match self.orphans.remove(hash) {
+            Some(orphan) => orphan,
+            None => return,
+        };
+        let parent = orphan.block.header().previous_hash();
+        if let Some(siblings) = self.by_parent.get_mut(parent) {
+            siblings.retain(|sibling| sibling != hash);
+            if siblings.is_empty() {
+                self.by_parent.remove(parent);
+            }
+        }
+    }
+}
+
+impl<T: Serializable + Clone> Default for OrphanPool<T> {
+    fn default() -> OrphanPool<T> {
+        OrphanPool::new(DEFAULT_MAX_ORPHANS, DEFAULT_ORPHAN_EXPIRY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::transaction::Transaction;
+
+    fn orphan(parent: u8, nonce: u32) -> Block<Transaction> {
+        BlockBuilder::new()
+            .previous_hash(Hash256::from([parent; 32]))
+            .timestamp(1500000000)
+            .nonce(nonce)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_orphan_pool() {
+        let mut pool = OrphanPool::new(3, 600);
+        assert!(pool.insert(orphan(1, 0), 1000).unwrap());
+        assert!(pool.insert(orphan(1, 1), 1100).unwrap());
+        assert!(pool.insert(orphan(2, 0), 1200).unwrap());
+        assert!(!pool.insert(orphan(2, 0), 1300).unwrap());
+        assert_eq!(3, pool.len());
+
+        // The oldest orphan makes way for a new one.
+        assert!(pool.insert(orphan(3, 0), 1400).unwrap());
+        assert!(!pool.contains(&orphan(1, 0).header_hash().unwrap()));
+        assert_eq!(vec![orphan(1, 1)], pool.take_children(&Hash256::from([1; 32])));
+        assert!(pool.take_children(&Hash256::from([1; 32])).is_empty());
+
+        pool.expire(1801);
+        assert_eq!(1, pool.len());
+        assert!(pool.contains(&orphan(3, 0).header_hash().unwrap()));
+    }
+}
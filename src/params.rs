@@ -0,0 +1,128 @@
+use crate::amount::{Amount, SATOSHIS_PER_BITCOIN};
+use crate::difficulty::Retarget;
+use crate::uint::U256;
+
+pub const MAINNET_MAGIC: u32 = 0xD9B4BEF9;
+pub const TESTNET_MAGIC: u32 = 0x0709110B;
+pub const REGTEST_MAGIC: u32 = 0xDAB5BFFA;
+
+// The number of blocks after which a coinbase output can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+const INITIAL_SUBSIDY: u64 = 50 * SATOSHIS_PER_BITCOIN;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+// Consensus and framing parameters for a chain. Custom chains can start from
+// one of the built-in networks and override individual fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainParams {
+    pub network: Network,
+    pub magic: u32,
+    // Easiest allowed proof-of-work target.
+    pub pow_limit: U256,
+    // Chain ID for merge-mined chains that accept auxiliary proof-of-work.
+    pub aux_pow_chain_id: Option<u32>,
+    // The time a retarget period should take, and the time per block. The
+    // target is adjusted every timespan / spacing blocks.
+    pub pow_target_timespan: u32,
+    pub pow_target_spacing: u32,
+    // Testnet rule: a block more than twice the spacing after its parent
+    // may use the easiest target.
+    pub pow_allow_min_difficulty_blocks: bool,
+    // Regtest rule: the target never changes.
+    pub pow_no_retargeting: bool,
+    // How the target is adjusted; Bitcoin's retarget for the built-in
+    // networks.
+    pub retarget: Retarget,
+    // How many blocks deep a coinbase has to be before it can be spent.
+    pub coinbase_maturity: u32,
+    // The number of blocks between halvings of the block subsidy.
+    pub subsidy_halving_interval: u32,
+}
+
+fn pow_limit(leading_zero_bytes: usize, first_byte: u8, ones: usize) -> U256 {
+    let mut limit = [0; 32];
+    limit[leading_zero_bytes] = first_byte;
+    for byte in limit.iter_mut().skip(leading_zero_bytes + 1).take(ones) {
+        *byte = 0xff;
+    }
+    U256::from_be_bytes(limit)
+}
+
+impl ChainParams {
+    pub fn mainnet() -> ChainParams {
+        ChainParams {
+            network: Network::Mainnet,
+            magic: MAINNET_MAGIC,
+            pow_limit: pow_limit(4, 0xff, 1),
+            aux_pow_chain_id: None,
+            pow_target_timespan: 14 * 24 * 60 * 60,
+            pow_target_spacing: 10 * 60,
+            pow_allow_min_difficulty_blocks: false,
+            pow_no_retargeting: false,
+            retarget: Retarget::Bitcoin,
+            coinbase_maturity: COINBASE_MATURITY,
+            subsidy_halving_interval: 210000,
+        }
+    }
+
+    pub fn testnet() -> ChainParams {
+        ChainParams {
+            network: Network::Testnet,
+            magic: TESTNET_MAGIC,
+            pow_limit: pow_limit(4, 0xff, 1),
+            aux_pow_chain_id: None,
+            pow_target_timespan: 14 * 24 * 60 * 60,
+            pow_target_spacing: 10 * 60,
+            pow_allow_min_difficulty_blocks: true,
+            pow_no_retargeting: false,
+            retarget: Retarget::Bitcoin,
+            coinbase_maturity: COINBASE_MATURITY,
+            subsidy_halving_interval: 210000,
+        }
+    }
+
+    pub fn regtest() -> ChainParams {
+        ChainParams {
+            network: Network::Regtest,
+            magic: REGTEST_MAGIC,
+            pow_limit: pow_limit(0, 0x7f, 2),
+            aux_pow_chain_id: None,
+            pow_target_timespan: 14 * 24 * 60 * 60,
+            pow_target_spacing: 10 * 60,
+            pow_allow_min_difficulty_blocks: true,
+            pow_no_retargeting: true,
+            retarget: Retarget::Bitcoin,
+            coinbase_maturity: COINBASE_MATURITY,
+            subsidy_halving_interval: 150,
+        }
+    }
+
+    // The number of blocks between target adjustments.
+    pub fn difficulty_adjustment_interval(&self) -> u32 {
+        self.pow_target_timespan / self.pow_target_spacing
+    }
+
+    // The new coins a block at `height` may create: 50 coins, halved every
+    // subsidy_halving_interval blocks until nothing is left.
+    pub fn block_subsidy(&self, height: u32) -> Amount {
+        match height / self.subsidy_halving_interval {
+            halvings if halvings < 64 => Amount::from_sat(INITIAL_SUBSIDY >> halvings),
+            _ => Amount::ZERO,
+        }
+    }
+
+    pub fn for_network(network: Network) -> ChainParams {
+        match network {
+            Network::Mainnet => ChainParams::mainnet(),
+            Network::Testnet => ChainParams::testnet(),
+            Network::Regtest => ChainParams::regtest(),
+        }
+    }
+}
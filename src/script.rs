@@ -0,0 +1,654 @@
+use byteorder::{ByteOrder, LittleEndian};
+use crate::error::BlockchainError;
+use crate::hash::{hash160, single_hash};
+use crate::io::{Read, Write};
+use crate::opcodes::*;
+use crate::prelude::*;
+use crate::signature::is_valid_signature_encoding;
+use crate::transaction::Witness;
+use crate::util::*;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Script(#[cfg_attr(feature = "serde", serde(with = "crate::util::hex"))] Vec<u8>);
+
+impl Script {
+    pub fn new() -> Script {
+        Script(Vec::new())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions { data: self.0.as_slice() }
+    }
+
+    // Scripts that can never be spent, so their outputs can be dropped.
+    pub fn is_unspendable(&self) -> bool {
+        self.0.first() == Some(&OP_RETURN) || self.0.len() > MAX_SCRIPT_SIZE
+    }
+
+    // As in Bitcoin Core, OP_RESERVED counts as a push along with the small
+    // integers around it.
+    pub fn is_push_only(&self) -> bool {
+        self.instructions()
+            .all(|instruction| match instruction {
+                     Ok(Instruction::PushBytes(_)) => true,
+                     Ok(Instruction::Op(opcode)) => opcode <= OP_16,
+                     Err(_) => false,
+                 })
+    }
+
+    // Disassembles the script in Bitcoin Core's format. Pushes of up to four
+    // bytes are shown as numbers, and with `decode_sighash`, signatures are
+    // shown with their sighash type, e.g. `3045...01[ALL]`.
+    pub fn to_asm(&self, decode_sighash: bool) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        for instruction in self.instructions() {
+            match instruction {
+                Ok(Instruction::PushBytes(data)) if data.len() <= 4 => {
+                    parts.push(script_num(data).to_string());
+                }
+                Ok(Instruction::PushBytes(data)) => {
+                    parts.push(push_asm(data, decode_sighash && !self.is_unspendable()));
+                }
+                Ok(Instruction::Op(opcode)) => parts.push(name(opcode).to_string()),
+                Err(_) => {
+                    parts.push("[error]".to_string());
+                    break;
+                }
+            }
+        }
+        parts.join(" ")
+    }
+
+    pub fn script_type(&self) -> ScriptType {
+        let bytes = self.0.as_slice();
+        if bytes.len() == 23 && bytes[0] == OP_HASH160 && bytes[1] == 20 &&
+           bytes[22] == OP_EQUAL {
+            return ScriptType::ScriptHash;
+        }
+        if let Some((version, program)) = self.witness_program() {
+            return match (version, program.len()) {
+                       (0, 20) => ScriptType::WitnessV0KeyHash,
+                       (0, 32) => ScriptType::WitnessV0ScriptHash,
+                       (1, 32) => ScriptType::WitnessV1Taproot,
+                       (0, _) => ScriptType::NonStandard,
+                       _ => ScriptType::WitnessUnknown,
+                   };
+        }
+        if bytes.first() == Some(&OP_RETURN) && Script::from(&bytes[1..]).is_push_only() {
+            return ScriptType::NullData;
+        }
+        if bytes.len() == 25 && bytes[0] == OP_DUP && bytes[1] == OP_HASH160 &&
+           bytes[2] == 20 && bytes[23] == OP_EQUALVERIFY && bytes[24] == OP_CHECKSIG {
+            return ScriptType::PubKeyHash;
+        }
+
+        let instructions: Vec<Instruction> = match self.instructions().collect() {
+            Ok(instructions) => instructions,
+            Err(_) => return ScriptType::NonStandard,
+        };
+        match instructions.as_slice() {
+            [Instruction::PushBytes(key), Instruction::Op(OP_CHECKSIG)] if is_pubkey(key) => {
+                ScriptType::PubKey
+            }
+            [Instruction::Op(m), keys @ .., Instruction::Op(n), Instruction::Op(OP_CHECKMULTISIG)]
+                if is_small_int(*m) && is_small_int(*n) && m <= n &&
+                   (n - OP_1 + 1) as usize == keys.len() &&
+                   keys.iter().all(|key| match *key {
+                                       Instruction::PushBytes(key) => is_pubkey(key),
+                                       _ => false,
+                                   }) => ScriptType::MultiSig,
+            _ => ScriptType::NonStandard,
+        }
+    }
+
+    // The script with every OP_CODESEPARATOR removed, as legacy signature
+    // hashing signs it. Anything after an invalid push is kept as is.
+    pub fn without_code_separators(&self) -> Script {
+        let mut result: Vec<u8> = Vec::with_capacity(self.0.len());
+        let mut instructions = self.instructions();
+        let mut start = 0;
+        loop {
+            let position = self.0.len() - instructions.data.len();
+            match instructions.next() {
+                Some(Ok(Instruction::Op(OP_CODESEPARATOR))) => {
+                    result.extend_from_slice(&self.0[start..position]);
+                    start = position + 1;
+                }
+                Some(Ok(_)) => {}
+                _ => break,
+            }
+        }
+        result.extend_from_slice(&self.0[start..]);
+
+        Script(result)
+    }
+
+    // BIP141 witness program: a version opcode followed by a single push of
+    // 2 to 40 bytes.
+    pub fn witness_program(&self) -> Option<(u8, &[u8])> {
+        let bytes = self.0.as_slice();
+        if bytes.len() < 4 || bytes.len() > 42 || bytes[1] as usize != bytes.len() - 2 {
+            return None;
+        }
+        match bytes[0] {
+            OP_0 => Some((0, &bytes[2..])),
+            OP_1..=OP_16 => Some((bytes[0] - OP_1 + 1, &bytes[2..])),
+            _ => None,
+        }
+    }
+
+    // Counts signature operations as the block sigop limit does. A
+    // multisig counts as 20 unless `accurate` is set and its key count is
+    // a small integer, as it is in redeem scripts. Counting stops at an
+    // invalid push.
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last = None;
+        for instruction in self.instructions() {
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(_) => break,
+            };
+            match instruction {
+                Instruction::Op(OP_CHECKSIG) |
+                Instruction::Op(OP_CHECKSIGVERIFY) => count += 1,
+                Instruction::Op(OP_CHECKMULTISIG) |
+                Instruction::Op(OP_CHECKMULTISIGVERIFY) => {
+                    count += match last {
+                        Some(Instruction::Op(n)) if accurate && is_small_int(n) => {
+                            (n - OP_1 + 1) as usize
+                        }
+                        _ => 20,
+                    }
+                }
+                _ => {}
+            }
+            last = Some(instruction);
+        }
+        count
+    }
+
+    // BIP16: the redeem script revealed by a scriptSig spending this
+    // pay-to-script-hash output: the last push of the scriptSig, which must
+    // be push-only. Checking it against the output's hash is left to
+    // executing the output script.
+    pub fn p2sh_redeem_script(&self, script_sig: &Script) -> Result<Script, BlockchainError> {
+        if self.script_type() != ScriptType::ScriptHash {
+            return Err(BlockchainError::InvalidScript("not a pay-to-script-hash output"));
+        }
+        if !script_sig.is_push_only() {
+            return Err(BlockchainError::InvalidScript("P2SH scriptSig is not push-only"));
+        }
+        match script_sig.instructions().last() {
+            Some(Ok(Instruction::PushBytes(data))) => Ok(Script::from(data)),
+            _ => Err(BlockchainError::InvalidScript("P2SH scriptSig has no redeem script")),
+        }
+    }
+
+    // The signature operations in the redeem script of a P2SH spend, which
+    // count towards the block limit in addition to the output's own.
+    pub fn p2sh_sigop_count(&self, script_sig: &Script) -> usize {
+        match self.p2sh_redeem_script(script_sig) {
+            Ok(redeem_script) => redeem_script.sigop_count(true),
+            Err(_) => 0,
+        }
+    }
+
+    // The witness program checked against the BIP141 length rules, or None
+    // if the script isn't one. Only version 0 programs are constrained here;
+    // later versions are left to future soft forks.
+    pub fn checked_witness_program(&self) -> Result<Option<(u8, &[u8])>, BlockchainError> {
+        match self.witness_program() {
+            Some((0, program)) if program.len() != 20 && program.len() != 32 => {
+                Err(BlockchainError::InvalidWitness("version 0 program must be 20 or 32 bytes"))
+            }
+            program => Ok(program),
+        }
+    }
+
+    // Whether the script is a witness program of a version with no spending
+    // rules yet. Such outputs are anyone-can-spend to nodes that predate the
+    // soft fork defining them, so they're relayed but not otherwise checked.
+    pub fn is_upgradable_witness_program(&self) -> bool {
+        match self.witness_program() {
+            Some((0, _)) => false,
+            Some((1, program)) => program.len() != 32,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    // An m-of-n multisig script, to use as a P2SH redeem script or a P2WSH
+    // witness script. The keys keep the given order, which is the order
+    // their signatures have to be in.
+    pub fn multisig(required: usize, keys: &[&[u8]]) -> Result<Script, BlockchainError> {
+        if required == 0 || required > keys.len() || keys.len() > 16 {
+            return Err(BlockchainError::InvalidScript("multisig needs 1 <= m <= n <= 16"));
+        }
+        if !keys.iter().all(|key| is_pubkey(key)) {
+            return Err(BlockchainError::InvalidScript("invalid public key"));
+        }
+        let mut script = vec![OP_1 + required as u8 - 1];
+        for key in keys {
+            push_data(&mut script, key);
+        }
+        script.push(OP_1 + keys.len() as u8 - 1);
+        script.push(OP_CHECKMULTISIG);
+
+        Ok(Script(script))
+    }
+
+    // The P2SH output script paying to this redeem script.
+    pub fn to_p2sh(&self) -> Script {
+        let mut script = vec![OP_HASH160, 20];
+        script.extend_from_slice(&hash160(&self.0));
+        script.push(OP_EQUAL);
+        Script(script)
+    }
+
+    // The P2WSH output script paying to this witness script.
+    pub fn to_p2wsh(&self) -> Script {
+        let mut script = vec![OP_0, 32];
+        script.extend_from_slice(single_hash(&self.0).as_bytes());
+        Script(script)
+    }
+
+    // The scriptSig spending a P2SH output with this multisig redeem script.
+    // `signatures` pairs the public key of each signer with its signature
+    // push (DER plus the sighash byte), in any order; exactly m are needed.
+    // CHECKMULTISIG pops one item too many, so the scriptSig starts with an
+    // OP_0 dummy, then has the signatures in key order and the redeem script.
+    pub fn multisig_script_sig(&self,
+                               signatures: &[(&[u8], &[u8])])
+                               -> Result<Script, BlockchainError> {
+        if self.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(BlockchainError::InvalidScript("redeem script is too large to push"));
+        }
+        let mut script = vec![OP_0];
+        for signature in self.multisig_signatures_in_order(signatures)? {
+            push_data(&mut script, signature);
+        }
+        push_data(&mut script, &self.0);
+
+        Ok(Script(script))
+    }
+
+    // The witness spending a P2WSH output with this multisig witness script,
+    // laid out like multisig_script_sig's scriptSig.
+    pub fn multisig_witness(&self,
+                            signatures: &[(&[u8], &[u8])])
+                            -> Result<Witness, BlockchainError> {
+        let mut items = vec![Vec::new()];
+        for signature in self.multisig_signatures_in_order(signatures)? {
+            items.push(signature.to_vec());
+        }
+        items.push(self.0.clone());
+
+        Ok(Witness::new(items))
+    }
+
+    fn multisig_signatures_in_order<'a>(&self,
+                                        signatures: &[(&[u8], &'a [u8])])
+                                        -> Result<Vec<&'a [u8]>, BlockchainError> {
+        if self.script_type() != ScriptType::MultiSig {
+            return Err(BlockchainError::InvalidScript("not a multisig script"));
+        }
+        let mut required = 0;
+        let mut keys = Vec::new();
+        for instruction in self.instructions() {
+            match instruction? {
+                Instruction::PushBytes(key) => keys.push(key),
+                Instruction::Op(opcode) if required == 0 => {
+                    required = (opcode - OP_1 + 1) as usize;
+                }
+                Instruction::Op(_) => {}
+            }
+        }
+        if signatures.len() != required {
+            return Err(BlockchainError::InvalidScript("wrong number of multisig signatures"));
+        }
+
+        let mut ordered = Vec::with_capacity(required);
+        for (key, signature) in signatures {
+            let position = keys.iter()
+                .position(|candidate| candidate == key)
+                .ok_or(BlockchainError::InvalidScript("signature key is not in the script"))?;
+            ordered.push((position, *signature));
+        }
+        ordered.sort_by_key(|(position, _)| *position);
+        if ordered.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(BlockchainError::InvalidScript("two signatures for the same key"));
+        }
+
+        Ok(ordered.into_iter().map(|(_, signature)| signature).collect())
+    }
+}
+
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+
+// The largest item a script can push.
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptType {
+    NonStandard,
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    MultiSig,
+    NullData,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    WitnessV1Taproot,
+    WitnessUnknown,
+}
+
+impl ScriptType {
+    // The name Bitcoin Core uses for the type in RPC output.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ScriptType::NonStandard => "nonstandard",
+            ScriptType::PubKey => "pubkey",
+            ScriptType::PubKeyHash => "pubkeyhash",
+            ScriptType::ScriptHash => "scripthash",
+            ScriptType::MultiSig => "multisig",
+            ScriptType::NullData => "nulldata",
+            ScriptType::WitnessV0KeyHash => "witness_v0_keyhash",
+            ScriptType::WitnessV0ScriptHash => "witness_v0_scripthash",
+            ScriptType::WitnessV1Taproot => "witness_v1_taproot",
+            ScriptType::WitnessUnknown => "witness_unknown",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    PushBytes(&'a [u8]),
+    Op(u8),
+}
+
+pub struct Instructions<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, BlockchainError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&opcode, rest) = self.data.split_first()?;
+        let (length_size, length) = match opcode {
+            0x01..=0x4b => (0, opcode as usize),
+            OP_PUSHDATA1 if !rest.is_empty() => (1, rest[0] as usize),
+            OP_PUSHDATA2 if rest.len() >= 2 => (2, LittleEndian::read_u16(rest) as usize),
+            OP_PUSHDATA4 if rest.len() >= 4 => (4, LittleEndian::read_u32(rest) as usize),
+            OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => {
+                self.data = &[];
+                return Some(Err(BlockchainError::InvalidScript("truncated push length")));
+            }
+            OP_0 => {
+                self.data = rest;
+                return Some(Ok(Instruction::PushBytes(&[])));
+            }
+            _ => {
+                self.data = rest;
+                return Some(Ok(Instruction::Op(opcode)));
+            }
+        };
+        let rest = &rest[length_size..];
+        if rest.len() < length {
+            self.data = &[];
+            return Some(Err(BlockchainError::InvalidScript("push past end of script")));
+        }
+        self.data = &rest[length..];
+
+        Some(Ok(Instruction::PushBytes(&rest[..length])))
+    }
+}
+
+// Appends the smallest push of `data`.
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        0..=0x4b => script.push(data.len() as u8),
+        0x4c..=0xff => script.extend_from_slice(&[OP_PUSHDATA1, data.len() as u8]),
+        _ => {
+            script.push(OP_PUSHDATA2);
+            script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+}
+
+fn is_small_int(opcode: u8) -> bool {
+    (OP_1..=OP_16).contains(&opcode)
+}
+
+fn is_pubkey(key: &[u8]) -> bool {
+    match key.len() {
+        33 => key[0] == 0x02 || key[0] == 0x03,
+        65 => key[0] == 0x04,
+        _ => false,
+    }
+}
+
+// Decodes a minimal-or-not little-endian sign-magnitude script number.
+fn script_num(data: &[u8]) -> i64 {
+    let mut value: i64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    match data.last() {
+        Some(last) if last & 0x80 != 0 => {
+            -(value & !(0x80 << (8 * (data.len() - 1))))
+        }
+        _ => value,
+    }
+}
+
+fn push_asm(data: &[u8], decode_sighash: bool) -> String {
+    if decode_sighash && is_valid_signature_encoding(data) {
+        let (sighash, signature) = data.split_last().unwrap();
+        let name = match *sighash {
+            0x01 => Some("ALL"),
+            0x02 => Some("NONE"),
+            0x03 => Some("SINGLE"),
+            0x81 => Some("ALL|ANYONECANPAY"),
+            0x82 => Some("NONE|ANYONECANPAY"),
+            0x83 => Some("SINGLE|ANYONECANPAY"),
+            _ => None,
+        };
+        if let Some(name) = name {
+            return format!("{}[{}]", to_hex(signature), name);
+        }
+    }
+    to_hex(data)
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(bytes: Vec<u8>) -> Script {
+        Script(bytes)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Script {
+    fn from(bytes: &'a [u8]) -> Script {
+        Script(bytes.to_vec())
+    }
+}
+
+impl Serializable for Script {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        VarInt(self.0.len() as u64).serialize_to(writer)?;
+        writer.write_all(self.0.as_slice())?;
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        VarInt(self.0.len() as u64).serialized_size() + self.0.len()
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Script::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        Ok(Script(read_bytes(reader, "script size", limits.max_script_size)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_witness_programs() {
+        let mut v0 = vec![OP_0, 20];
+        v0.extend_from_slice(&[0x11; 20]);
+        let v0 = Script::from(v0);
+        assert_eq!(Some((0, &[0x11; 20][..])), v0.checked_witness_program().unwrap());
+        assert_eq!(ScriptType::WitnessV0KeyHash, v0.script_type());
+        assert!(!v0.is_upgradable_witness_program());
+
+        let bad_v0 = Script::from(vec![OP_0, 3, 0x01, 0x02, 0x03]);
+        assert!(matches!(bad_v0.checked_witness_program(),
+                         Err(BlockchainError::InvalidWitness(_))));
+        assert_eq!(ScriptType::NonStandard, bad_v0.script_type());
+
+        let mut taproot = vec![OP_1, 32];
+        taproot.extend_from_slice(&[0x22; 32]);
+        assert!(!Script::from(taproot).is_upgradable_witness_program());
+
+        let v1_short = Script::from(vec![OP_1, 2, 0x4e, 0x73]);
+        assert!(v1_short.is_upgradable_witness_program());
+        let v16 = Script::from(vec![OP_16, 2, 0x4e, 0x73]);
+        assert_eq!(Some((16, &[0x4e, 0x73][..])), v16.checked_witness_program().unwrap());
+        assert_eq!(ScriptType::WitnessUnknown, v16.script_type());
+        assert!(v16.is_upgradable_witness_program());
+
+        // A push of the wrong size isn't a witness program at all.
+        let not_program = Script::from(vec![OP_0, 1, 0x01]);
+        assert_eq!(None, not_program.checked_witness_program().unwrap());
+        assert!(!not_program.is_upgradable_witness_program());
+    }
+
+    #[test]
+    fn test_p2sh_sigops() {
+        let mut redeem_script = vec![OP_1 + 1];
+        for key in 0..3 {
+            redeem_script.push(33);
+            redeem_script.push(0x02);
+            redeem_script.extend_from_slice(&[key; 32]);
+        }
+        redeem_script.extend_from_slice(&[OP_1 + 2, OP_CHECKMULTISIG]);
+        let redeem_script = Script::from(redeem_script);
+        assert_eq!(ScriptType::MultiSig, redeem_script.script_type());
+        assert_eq!(20, redeem_script.sigop_count(false));
+        assert_eq!(3, redeem_script.sigop_count(true));
+
+        let mut output = vec![OP_HASH160, 20];
+        output.extend_from_slice(&[0x33; 20]);
+        output.push(OP_EQUAL);
+        let output = Script::from(output);
+        assert_eq!(0, output.sigop_count(true));
+
+        let mut script_sig = vec![OP_0, 2, 0x30, 0x01, OP_PUSHDATA1, 105];
+        script_sig.extend_from_slice(redeem_script.as_bytes());
+        let script_sig = Script::from(script_sig);
+        assert_eq!(redeem_script, output.p2sh_redeem_script(&script_sig).unwrap());
+        assert_eq!(3, output.p2sh_sigop_count(&script_sig));
+        // OP_RESERVED doesn't stop the redeem script's sigops counting.
+        let mut reserved = vec![0x50];
+        reserved.extend_from_slice(script_sig.as_bytes());
+        let reserved = Script::from(reserved);
+        assert!(reserved.is_push_only());
+        assert_eq!(3, output.p2sh_sigop_count(&reserved));
+
+        let not_push_only = Script::from(vec![OP_CHECKSIG, 1, 0x51]);
+        assert!(matches!(output.p2sh_redeem_script(&not_push_only),
+                         Err(BlockchainError::InvalidScript(_))));
+        assert_eq!(0, output.p2sh_sigop_count(&not_push_only));
+        assert!(matches!(redeem_script.p2sh_redeem_script(&script_sig),
+                         Err(BlockchainError::InvalidScript(_))));
+    }
+
+    #[test]
+    fn test_multisig() {
+        // A 2-of-3 with uncompressed keys.
+        let keys = [from_hex("0491bba2510912a5bd37da1fb5b1673010e43d2c6d812c514e91bfa9f2eb129e1c\
+                              183329db55bd868e209aac2fbf02cb33d98fe74bf23f0c235d6126b1d8334f86")
+                        .unwrap(),
+                    from_hex("04865c40293a680cb9c020e7b1e106d8c1916d3cef99aa431a56d253e69256dac0\
+                              9ef122b1a986818a7cb624532f062c1d1f8722084861c5c3291ccffef4ec6874")
+                        .unwrap(),
+                    from_hex("048d2455d2403e08708fc1f556002f1b6cd83f992d085097f9974ab08a28838f07\
+                              896fbab08f39495e15fa6fad6edbfb1e754e35fa1c7844c41f322a1863d46213")
+                        .unwrap()];
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+        let redeem_script = Script::multisig(2, &keys).unwrap();
+        assert_eq!(ScriptType::MultiSig, redeem_script.script_type());
+        let mut expected = vec![OP_1 + 1];
+        for key in &keys {
+            expected.push(65);
+            expected.extend_from_slice(key);
+        }
+        expected.extend_from_slice(&[OP_1 + 2, OP_CHECKMULTISIG]);
+        assert_eq!(expected, redeem_script.as_bytes());
+        assert_eq!("a9142782cfc2062a4e28b4ad51b6e42c480c0177c42d87",
+                   to_hex(redeem_script.to_p2sh().as_bytes()));
+
+        // Signatures go in key order whatever order they're given in.
+        let first: &[u8] = &[0x30, 0x01];
+        let third: &[u8] = &[0x30, 0x03];
+        let script_sig = redeem_script
+            .multisig_script_sig(&[(keys[2], third), (keys[0], first)])
+            .unwrap();
+        let pushes: Vec<Instruction> = script_sig.instructions().map(|i| i.unwrap()).collect();
+        assert_eq!(vec![Instruction::PushBytes(&[]),
+                        Instruction::PushBytes(first),
+                        Instruction::PushBytes(third),
+                        Instruction::PushBytes(redeem_script.as_bytes())],
+                   pushes);
+        assert_eq!(redeem_script,
+                   redeem_script.to_p2sh().p2sh_redeem_script(&script_sig).unwrap());
+        let witness = redeem_script.multisig_witness(&[(keys[2], third), (keys[0], first)])
+            .unwrap();
+        assert_eq!(&[vec![], first.to_vec(), third.to_vec(), redeem_script.clone().into_bytes()],
+                   witness.items());
+
+        assert!(redeem_script.multisig_script_sig(&[(keys[0], first)]).is_err());
+        assert!(redeem_script.multisig_script_sig(&[(keys[0], first), (keys[0], third)])
+                    .is_err());
+        assert!(redeem_script.multisig_script_sig(&[(keys[0], first), (&[0x02; 33], third)])
+                    .is_err());
+        assert!(Script::multisig(3, &keys[..2]).is_err());
+        assert!(Script::multisig(1, &[&[0x05; 33]]).is_err());
+
+        // The P2WSH example from BIP173.
+        let mut key = vec![33];
+        key.extend_from_slice(&from_hex("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f28\
+                                         15b16f81798")
+                                   .unwrap());
+        key.push(OP_CHECKSIG);
+        assert_eq!("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                   to_hex(Script::from(key).to_p2wsh().as_bytes()));
+    }
+}
@@ -0,0 +1,513 @@
+use byteorder::LittleEndian;
+use crate::amount::Amount;
+use crate::error::BlockchainError;
+use crate::hash::{Hash256, double_hash, single_hash, tagged_hash};
+use crate::io::WriteBytesExt;
+use crate::prelude::*;
+use crate::script::Script;
+use crate::transaction::{Output, Transaction};
+use crate::util::*;
+
+// Taproot only: signs like SIGHASH_ALL, with a 64-byte signature.
+pub const SIGHASH_DEFAULT: u32 = 0x00;
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+// Single SHA256 hashes of the parts of a transaction that every input's
+// signature hash covers. Segwit v0 uses their double hashes, which are
+// the SHA256 of these.
+struct TransactionHashes {
+    prevouts: Hash256,
+    sequences: Hash256,
+    outputs: Hash256,
+}
+
+// Hashes of the outputs spent by a transaction, for taproot, along with
+// the outputs they were computed from.
+struct SpentHashes {
+    prevouts: Vec<Output>,
+    amounts: Hash256,
+    scripts: Hash256,
+}
+
+// Computes the signature hashes of a transaction's inputs, for legacy,
+// BIP143 (segwit v0) and BIP341 (taproot) signatures. The hashes that are
+// shared by every input are computed once and reused, so signing all of
+// a transaction's inputs takes linear time.
+pub struct SighashCache<'a> {
+    transaction: &'a Transaction,
+    hashes: Option<TransactionHashes>,
+    spent: Option<SpentHashes>,
+}
+
+impl<'a> SighashCache<'a> {
+    pub fn new(transaction: &'a Transaction) -> SighashCache<'a> {
+        SighashCache {
+            transaction: transaction,
+            hashes: None,
+            spent: None,
+        }
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        self.transaction
+    }
+
+    // The original signature hash. Any 32-bit type is accepted and signed
+    // as is. For SIGHASH_SINGLE without a matching output this is the
+    // value one, as in Bitcoin Core.
+    pub fn legacy_signature_hash(&self,
+                                 index: usize,
+                                 script_code: &Script,
+                                 sighash_type: u32)
+                                 -> Result<Hash256, BlockchainError> {
+        let transaction = self.transaction;
+        check_index(transaction, index)?;
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if base_type == SIGHASH_SINGLE && index >= transaction.outputs().len() {
+            let mut one = [0; 32];
+            one[0] = 1;
+            return Ok(Hash256::from(one));
+        }
+
+        let mut data: Vec<u8> = Vec::new();
+        data.write_u32::<LittleEndian>(transaction.version())?;
+        let input_count = if anyone_can_pay {
+            1
+        } else {
+            transaction.inputs().len()
+        };
+        VarInt(input_count as u64).serialize_to(&mut data)?;
+        for (i, input) in transaction.inputs().iter().enumerate() {
+            let signed = i == index;
+            if anyone_can_pay && !signed {
+                continue;
+            }
+            input.previous_output().serialize_to(&mut data)?;
+            if signed {
+                script_code.without_code_separators().serialize_to(&mut data)?;
+            } else {
+                Script::new().serialize_to(&mut data)?;
+            }
+            if !signed && (base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE) {
+                data.write_u32::<LittleEndian>(0)?;
+            } else {
+                input.sequence().serialize_to(&mut data)?;
+            }
+        }
+        match base_type {
+            SIGHASH_NONE => VarInt(0).serialize_to(&mut data)?,
+            SIGHASH_SINGLE => {
+                VarInt(index as u64 + 1).serialize_to(&mut data)?;
+                // Earlier outputs are blanked to a value of -1 and no script.
+                for _ in 0..index {
+                    data.write_u64::<LittleEndian>(u64::MAX)?;
+                    Script::new().serialize_to(&mut data)?;
+                }
+                transaction.outputs()[index].serialize_to(&mut data)?;
+            }
+            _ => {
+                VarInt(transaction.outputs().len() as u64).serialize_to(&mut data)?;
+                for output in transaction.outputs() {
+                    output.serialize_to(&mut data)?;
+                }
+            }
+        }
+        transaction.lock_time().serialize_to(&mut data)?;
+        data.write_u32::<LittleEndian>(sighash_type)?;
+
+        Ok(double_hash(&data))
+    }
+
+    // BIP143 signature hash for segwit v0 inputs. `value` is the amount of
+    // the output being spent.
+    pub fn segwit_v0_signature_hash(&mut self,
+                                    index: usize,
+                                    script_code: &Script,
+                                    value: Amount,
+                                    sighash_type: u32)
+                                    -> Result<Hash256, BlockchainError> {
+        let transaction = self.transaction;
+        check_index(transaction, index)?;
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let hashes = self.transaction_hashes()?;
+        let zero = Hash256::default();
+
+        let mut data: Vec<u8> = Vec::new();
+        data.write_u32::<LittleEndian>(transaction.version())?;
+        if anyone_can_pay {
+            zero.serialize_to(&mut data)?;
+        } else {
+            single_hash(hashes.prevouts.as_bytes()).serialize_to(&mut data)?;
+        }
+        if anyone_can_pay || base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+            zero.serialize_to(&mut data)?;
+        } else {
+            single_hash(hashes.sequences.as_bytes()).serialize_to(&mut data)?;
+        }
+        let input = &transaction.inputs()[index];
+        input.previous_output().serialize_to(&mut data)?;
+        script_code.serialize_to(&mut data)?;
+        value.serialize_to(&mut data)?;
+        input.sequence().serialize_to(&mut data)?;
+        if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            single_hash(hashes.outputs.as_bytes()).serialize_to(&mut data)?;
+        } else if base_type == SIGHASH_SINGLE && index < transaction.outputs().len() {
+            double_hash(&transaction.outputs()[index].serialize()?).serialize_to(&mut data)?;
+        } else {
+            zero.serialize_to(&mut data)?;
+        }
+        transaction.lock_time().serialize_to(&mut data)?;
+        data.write_u32::<LittleEndian>(sighash_type)?;
+
+        Ok(double_hash(&data))
+    }
+
+    // BIP341 signature hash for taproot inputs. `prevouts` are the outputs
+    // spent by every input of the transaction, in order. The first call
+    // fixes them, and later calls with different ones are an error. `annex`
+    // is the last witness item if it starts with 0x50. For script path
+    // spends, `leaf` is the tapleaf hash and the position of the last
+    // executed OP_CODESEPARATOR (0xffffffff for none).
+    pub fn taproot_signature_hash(&mut self,
+                                  index: usize,
+                                  prevouts: &[Output],
+                                  annex: Option<&[u8]>,
+                                  leaf: Option<(Hash256, u32)>,
+                                  sighash_type: u32)
+                                  -> Result<Hash256, BlockchainError> {
+        let transaction = self.transaction;
+        check_index(transaction, index)?;
+        if prevouts.len() != transaction.inputs().len() {
+            return Err(BlockchainError::InvalidSighash("wrong number of prevouts"));
+        }
+        // Checked even when they aren't hashed, so that the first call fixes
+        // them whatever its type.
+        self.spent_hashes(prevouts)?;
+        match sighash_type {
+            0x00..=0x03 | 0x81..=0x83 => {}
+            _ => return Err(BlockchainError::InvalidSighash("invalid taproot sighash type")),
+        }
+        let base_type = sighash_type & 0x03;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if base_type == SIGHASH_SINGLE && index >= transaction.outputs().len() {
+            return Err(BlockchainError::InvalidSighash("no output for SIGHASH_SINGLE"));
+        }
+        if annex.is_some_and(|annex| annex.first() != Some(&0x50)) {
+            return Err(BlockchainError::InvalidSighash("annex must start with 0x50"));
+        }
+
+        // The epoch, then the message.
+        let mut data: Vec<u8> = vec![0x00, sighash_type as u8];
+        data.write_u32::<LittleEndian>(transaction.version())?;
+        transaction.lock_time().serialize_to(&mut data)?;
+        if !anyone_can_pay {
+            let hashes = self.transaction_hashes()?;
+            let (prevout_hash, sequence_hash, output_hash) =
+                (hashes.prevouts, hashes.sequences, hashes.outputs);
+            let spent = self.spent_hashes(prevouts)?;
+            prevout_hash.serialize_to(&mut data)?;
+            spent.amounts.serialize_to(&mut data)?;
+            spent.scripts.serialize_to(&mut data)?;
+            sequence_hash.serialize_to(&mut data)?;
+            if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+                output_hash.serialize_to(&mut data)?;
+            }
+        } else if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            self.transaction_hashes()?.outputs.serialize_to(&mut data)?;
+        }
+        data.push(2 * leaf.is_some() as u8 + annex.is_some() as u8);
+        let input = &transaction.inputs()[index];
+        if anyone_can_pay {
+            input.previous_output().serialize_to(&mut data)?;
+            prevouts[index].serialize_to(&mut data)?;
+            input.sequence().serialize_to(&mut data)?;
+        } else {
+            data.write_u32::<LittleEndian>(index as u32)?;
+        }
+        if let Some(annex) = annex {
+            let mut serialized: Vec<u8> = Vec::new();
+            VarInt(annex.len() as u64).serialize_to(&mut serialized)?;
+            serialized.extend_from_slice(annex);
+            single_hash(&serialized).serialize_to(&mut data)?;
+        }
+        if base_type == SIGHASH_SINGLE {
+            single_hash(&transaction.outputs()[index].serialize()?).serialize_to(&mut data)?;
+        }
+        if let Some((leaf_hash, code_separator)) = leaf {
+            leaf_hash.serialize_to(&mut data)?;
+            // The key version.
+            data.push(0x00);
+            data.write_u32::<LittleEndian>(code_separator)?;
+        }
+
+        Ok(tagged_hash("TapSighash", &data))
+    }
+
+    fn transaction_hashes(&mut self) -> Result<&TransactionHashes, BlockchainError> {
+        if self.hashes.is_none() {
+            let transaction = self.transaction;
+            let mut prevouts: Vec<u8> = Vec::new();
+            let mut sequences: Vec<u8> = Vec::new();
+            for input in transaction.inputs() {
+                input.previous_output().serialize_to(&mut prevouts)?;
+                input.sequence().serialize_to(&mut sequences)?;
+            }
+            let mut outputs: Vec<u8> = Vec::new();
+            for output in transaction.outputs() {
+                output.serialize_to(&mut outputs)?;
+            }
+            self.hashes = Some(TransactionHashes {
+                                   prevouts: single_hash(&prevouts),
+                                   sequences: single_hash(&sequences),
+                                   outputs: single_hash(&outputs),
+                               });
+        }
+
+        Ok(self.hashes.as_ref().unwrap())
+    }
+
+    fn spent_hashes(&mut self, prevouts: &[Output]) -> Result<&SpentHashes, BlockchainError> {
+        if self.spent.as_ref().is_some_and(|spent| spent.prevouts != prevouts) {
+            return Err(BlockchainError::InvalidSighash("prevouts differ from an earlier call"));
+        }
+        if self.spent.is_none() {
+            let mut amounts: Vec<u8> = Vec::new();
+            let mut scripts: Vec<u8> = Vec::new();
+            for output in prevouts {
+                output.value().serialize_to(&mut amounts)?;
+                output.script().serialize_to(&mut scripts)?;
+            }
+            self.spent = Some(SpentHashes {
+                                  prevouts: prevouts.to_vec(),
+                                  amounts: single_hash(&amounts),
+                                  scripts: single_hash(&scripts),
+                              });
+        }
+
+        Ok(self.spent.as_ref().unwrap())
+    }
+}
+
+fn check_index(transaction: &Transaction, index: usize) -> Result<(), BlockchainError> {
+    if index >= transaction.inputs().len() {
+        return Err(BlockchainError::InvalidSighash("input index out of range"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hash::Hash256;
+    use crate::locktime::{LockTime, Sequence};
+    use crate::transaction::{Input, Outpoint};
+
+    // The native P2WPKH and P2SH-P2WPKH examples from BIP143.
+    const P2WPKH_TX: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4\
+                             e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b30\
+                             9fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9\
+                             148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976\
+                             a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+    const P2SH_P2WPKH_TX: &str = "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d\
+                                  3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7\
+                                  f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b\
+                                  1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000";
+
+    fn test_transaction() -> Transaction {
+        let inputs: Vec<Input> = (0..3)
+            .map(|i| {
+                     Input::new(Outpoint::new(Hash256::from([i; 32]), i as u32),
+                                Script::new(),
+                                Sequence::MAX)
+                 })
+            .collect();
+        let outputs = [Output::new(Amount::from_sat(1000), Script::from(vec![0x51])),
+                       Output::new(Amount::from_sat(2000), Script::from(vec![0x52]))];
+        Transaction::new(2, &inputs, &outputs, LockTime::ZERO)
+    }
+
+    #[test]
+    fn test_segwit_v0() {
+        let transaction = Transaction::from_hex(P2WPKH_TX).unwrap();
+        let script_code =
+            Script::from_hex("1976a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+        let hash = SighashCache::new(&transaction)
+            .segwit_v0_signature_hash(1, &script_code, Amount::from_sat(600000000), SIGHASH_ALL)
+            .unwrap();
+        assert_eq!("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670",
+                   to_hex(hash.as_bytes()));
+
+        let transaction = Transaction::from_hex(P2SH_P2WPKH_TX).unwrap();
+        let script_code =
+            Script::from_hex("1976a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap();
+        let hash = SighashCache::new(&transaction)
+            .segwit_v0_signature_hash(0, &script_code, Amount::from_sat(1000000000), SIGHASH_ALL)
+            .unwrap();
+        assert_eq!("64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6",
+                   to_hex(hash.as_bytes()));
+    }
+
+    #[test]
+    fn test_legacy() {
+        let transaction = test_transaction();
+        let cache = SighashCache::new(&transaction);
+        let script_code = Script::from(vec![0x51, 0xab, 0x52]);
+        let hash = cache.legacy_signature_hash(0, &script_code, SIGHASH_ALL).unwrap();
+        assert_eq!(hash,
+                   cache
+                       .legacy_signature_hash(0, &Script::from(vec![0x51, 0x52]), SIGHASH_ALL)
+                       .unwrap());
+        assert!(hash != cache.legacy_signature_hash(1, &script_code, SIGHASH_ALL).unwrap());
+
+        // Only the signed input is covered with ANYONECANPAY, and no outputs
+        // with SIGHASH_NONE.
+        let mut changed = transaction.clone();
+        changed.push_input(Input::new(Outpoint::null(), Script::new(), Sequence::MAX));
+        changed.push_output(Output::new(Amount::from_sat(3000), Script::new()));
+        let changed_cache = SighashCache::new(&changed);
+        let anyone_can_pay = SIGHASH_NONE | SIGHASH_ANYONECANPAY;
+        assert_eq!(cache.legacy_signature_hash(1, &script_code, anyone_can_pay).unwrap(),
+                   changed_cache
+                       .legacy_signature_hash(1, &script_code, anyone_can_pay)
+                       .unwrap());
+        assert!(cache.legacy_signature_hash(1, &script_code, SIGHASH_NONE).unwrap() !=
+                changed_cache.legacy_signature_hash(1, &script_code, SIGHASH_NONE).unwrap());
+
+        // SIGHASH_SINGLE without a matching output signs the value one.
+        let mut one = [0; 32];
+        one[0] = 1;
+        assert_eq!(Hash256::from(one),
+                   cache.legacy_signature_hash(2, &script_code, SIGHASH_SINGLE).unwrap());
+        assert!(matches!(cache.legacy_signature_hash(3, &script_code, SIGHASH_ALL),
+                         Err(BlockchainError::InvalidSighash(_))));
+    }
+
+    // Input 0 of the BIP143 example spends a P2PK output with a legacy
+    // signature, which verifies against the first hash. The others are from
+    // a separate implementation of the signature hash written from Bitcoin
+    // Core's SignatureHash, over test_transaction().
+    #[test]
+    fn test_legacy_vectors() {
+        let transaction = Transaction::from_hex(P2WPKH_TX).unwrap();
+        let script_code = Script::from_hex("232103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c24\
+                                            1ce9fc198bd25432ac")
+            .unwrap();
+        let hash = SighashCache::new(&transaction)
+            .legacy_signature_hash(0, &script_code, SIGHASH_ALL)
+            .unwrap();
+        assert_eq!("63cec688ee06a91e913875356dd4dea2f8e0f2a2659885372da2a37e32c7532e",
+                   to_hex(hash.as_bytes()));
+
+        let transaction = test_transaction();
+        let cache = SighashCache::new(&transaction);
+        let script_code = Script::from(vec![0x51, 0xab, 0x52]);
+        let vectors = [(0, SIGHASH_ALL,
+                        "ccfe257e1d84b23be8ec3766aa8d0bdfc0d169a4fd4a6be7a71c1f82c7ba7357"),
+                       (1, SIGHASH_NONE,
+                        "9f900309b98ab32af4d9237b63057a1d4905c68c70cd7633b1b8dde84cf931d5"),
+                       (1, SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                        "d71e1035fd7084b3b7571532461f437aa0a54646e560ee2e7c081e50e27680e5"),
+                       (1, SIGHASH_SINGLE,
+                        "5ded47849cf447d4ccde574e3f960a7e1e95405133ba1a300e914102239bc14c"),
+                       (0, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                        "3dcf2170a5676f37ca28dd5abe603476bfb4e28bc40c525126901dd0f4e5bc5d"),
+                       (2, SIGHASH_SINGLE,
+                        "0100000000000000000000000000000000000000000000000000000000000000")];
+        for (index, hash_type, expected) in &vectors {
+            let hash = cache.legacy_signature_hash(*index, &script_code, *hash_type).unwrap();
+            assert_eq!(*expected, to_hex(hash.as_bytes()));
+        }
+    }
+
+    // From a separate implementation of BIP341's SigMsg, over
+    // test_transaction() and the prevouts in test_taproot().
+    #[test]
+    fn test_taproot_vectors() {
+        let transaction = test_transaction();
+        let prevouts: Vec<Output> = (0..3)
+            .map(|i| Output::new(Amount::from_sat(5000 + i), Script::from(vec![0x51, 0x20])))
+            .collect();
+        let mut cache = SighashCache::new(&transaction);
+        let leaf = Hash256::from([7; 32]);
+        let vectors =
+            [(0, SIGHASH_DEFAULT, None, None,
+              "d11d4cdce65b18570bc113dc9a7a87793db74246c935de7e0b1fdd2e13084888"),
+             (1, SIGHASH_ALL, None, None,
+              "79a23af4f2810937056a0f225d494ebdd8a534ddd3e00b2bd596cb8fc4d8601c"),
+             (1, SIGHASH_NONE, None, None,
+              "ca1f93f76f4242a1099eb241bdd96a056e7770f81bdea6b031b29dbc9b4dbb42"),
+             (1, SIGHASH_SINGLE, None, None,
+              "86bf6344e22ddcfcf861a2de2b2838da80813d9728b845e3980a211b5031db50"),
+             (2, SIGHASH_ALL | SIGHASH_ANYONECANPAY, None, None,
+              "1d434e623687e4fda05f67545da3d89e8ebce8f8e67f92e4902b47fb3a3a4ae5"),
+             (0, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, Some(&[0x50, 0x01][..]), None,
+              "f297bc7f4d1bb25aacf8e4f42d8acf395938061c5eb54d839757a2012eaf85b8"),
+             (0, SIGHASH_ALL, None, Some((leaf, 0xffffffff)),
+              "5d64f28443006349e7654dc621009f039eee2bbc53b3b606f96be578f5ada0e1"),
+             (1, SIGHASH_NONE | SIGHASH_ANYONECANPAY, None, Some((leaf, 3)),
+              "766a7ac0c2253c980ec0736e70115386a1114f3ec405db5aa90c6f3b69c1d9b1")];
+        for (index, hash_type, annex, leaf, expected) in &vectors {
+            let hash = cache
+                .taproot_signature_hash(*index, &prevouts, *annex, *leaf, *hash_type)
+                .unwrap();
+            assert_eq!(*expected, to_hex(hash.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_taproot() {
+        let transaction = test_transaction();
+        let prevouts: Vec<Output> = (0..3)
+            .map(|i| Output::new(Amount::from_sat(5000 + i), Script::from(vec![0x51, 0x20])))
+            .collect();
+        let mut cache = SighashCache::new(&transaction);
+        let default = cache
+            .taproot_signature_hash(0, &prevouts, None, None, SIGHASH_DEFAULT)
+            .unwrap();
+        let all = cache
+            .taproot_signature_hash(0, &prevouts, None, None, SIGHASH_ALL)
+            .unwrap();
+        assert!(default != all);
+        assert!(all !=
+                cache
+                    .taproot_signature_hash(0, &prevouts, Some(&[0x50]), None, SIGHASH_ALL)
+                    .unwrap());
+        assert!(all !=
+                cache
+                    .taproot_signature_hash(0,
+                                            &prevouts,
+                                            None,
+                                            Some((Hash256::from([7; 32]), 0xffffffff)),
+                                            SIGHASH_ALL)
+                    .unwrap());
+
+        assert!(matches!(cache.taproot_signature_hash(2, &prevouts, None, None, SIGHASH_SINGLE),
+                         Err(BlockchainError::InvalidSighash(_))));
+        assert!(matches!(cache.taproot_signature_hash(0, &prevouts, None, None, 0x04),
+                         Err(BlockchainError::InvalidSighash(_))));
+        assert!(matches!(cache.taproot_signature_hash(0, &prevouts[..2], None, None, SIGHASH_ALL),
+                         Err(BlockchainError::InvalidSighash(_))));
+        assert!(matches!(cache.taproot_signature_hash(0, &prevouts, Some(&[0x51]), None, 0x01),
+                         Err(BlockchainError::InvalidSighash(_))));
+
+        // The cache holds on to the first prevouts it's given.
+        let mut other = prevouts.clone();
+        other[1] = Output::new(Amount::from_sat(1), Script::from(vec![0x51, 0x20]));
+        for hash_type in &[SIGHASH_ALL, SIGHASH_ALL | SIGHASH_ANYONECANPAY] {
+            assert!(matches!(cache.taproot_signature_hash(0, &other, None, None, *hash_type),
+                             Err(BlockchainError::InvalidSighash(_))));
+        }
+        let mut cache = SighashCache::new(&transaction);
+        cache
+            .taproot_signature_hash(0, &prevouts, None, None, SIGHASH_ALL | SIGHASH_ANYONECANPAY)
+            .unwrap();
+        assert!(matches!(cache.taproot_signature_hash(1, &other, None, None, SIGHASH_ALL),
+                         Err(BlockchainError::InvalidSighash(_))));
+    }
+}
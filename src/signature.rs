@@ -0,0 +1,292 @@
+use crate::error::BlockchainError;
+use crate::prelude::*;
+use crate::uint::U256;
+
+// The order of the secp256k1 group, big-endian.
+const CURVE_ORDER: [u8; 32] = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                               0xff, 0xff, 0xff, 0xff, 0xfe, 0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48,
+                               0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41];
+
+// How closely a DER signature has to follow the rules. Lax accepts what
+// OpenSSL historically did; Der is BIP66's strict encoding; LowS further
+// requires s to be in the lower half of the range, as relay policy does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strictness {
+    Lax,
+    Der,
+    LowS,
+}
+
+// An ECDSA signature as its r and s values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+    r: U256,
+    s: U256,
+}
+
+impl Signature {
+    pub fn new(r: U256, s: U256) -> Signature {
+        Signature { r: r, s: s }
+    }
+
+    pub fn r(&self) -> U256 {
+        self.r
+    }
+
+    pub fn s(&self) -> U256 {
+        self.s
+    }
+
+    pub fn from_der(data: &[u8], strictness: Strictness) -> Result<Signature, BlockchainError> {
+        if strictness >= Strictness::Der {
+            // The strict check expects a trailing sighash byte.
+            let mut with_sighash = data.to_vec();
+            with_sighash.push(0x01);
+            if !is_valid_signature_encoding(&with_sighash) {
+                return Err(BlockchainError::InvalidSignature("not strict DER"));
+            }
+        }
+        let signature = parse_der(data)?;
+        if strictness == Strictness::LowS && !signature.is_low_s() {
+            return Err(BlockchainError::InvalidSignature("s is not low"));
+        }
+
+        Ok(signature)
+    }
+
+    pub fn to_der(&self) -> Vec<u8> {
+        let r = der_integer(&self.r);
+        let s = der_integer(&self.s);
+        let mut der = Vec::with_capacity(6 + r.len() + s.len());
+        der.push(0x30);
+        der.push((4 + r.len() + s.len()) as u8);
+        for integer in &[r, s] {
+            der.push(0x02);
+            der.push(integer.len() as u8);
+            der.extend_from_slice(integer);
+        }
+        der
+    }
+
+    // A signature as pushed in a scriptSig or witness: DER followed by the
+    // sighash type byte.
+    pub fn from_script_push(data: &[u8],
+                            strictness: Strictness)
+                            -> Result<(Signature, u8), BlockchainError> {
+        match data.split_last() {
+            Some((sighash_type, der)) => {
+                Ok((Signature::from_der(der, strictness)?, *sighash_type))
+            }
+            None => Err(BlockchainError::InvalidSignature("empty signature")),
+        }
+    }
+
+    pub fn to_script_push(&self, sighash_type: u8) -> Vec<u8> {
+        let mut data = self.to_der();
+        data.push(sighash_type);
+        data
+    }
+
+    // The 64-byte form: r and s, each 32 bytes big-endian.
+    pub fn from_compact(data: &[u8]) -> Result<Signature, BlockchainError> {
+        if data.len() != 64 {
+            return Err(BlockchainError::InvalidLength {
+                           expected: 64,
+                           found: data.len(),
+                       });
+        }
+        let mut r = [0; 32];
+        let mut s = [0; 32];
+        r.copy_from_slice(&data[..32]);
+        s.copy_from_slice(&data[32..]);
+
+        check_range(Signature::new(U256::from_be_bytes(r), U256::from_be_bytes(s)))
+    }
+
+    pub fn to_compact(&self) -> [u8; 64] {
+        let mut compact = [0; 64];
+        compact[..32].copy_from_slice(&self.r.to_be_bytes());
+        compact[32..].copy_from_slice(&self.s.to_be_bytes());
+        compact
+    }
+
+    // Whether s is at most half the curve order (BIP62/BIP146).
+    pub fn is_low_s(&self) -> bool {
+        self.s <= U256::from_be_bytes(CURVE_ORDER) >> 1
+    }
+
+    // The equivalent signature with a low s value. An s above the curve
+    // order, which only new() can produce, is left as it is.
+    pub fn normalize_s(&self) -> Signature {
+        if self.is_low_s() {
+            return *self;
+        }
+        match U256::from_be_bytes(CURVE_ORDER).checked_sub(self.s) {
+            Some(s) => Signature::new(self.r, s),
+            None => *self,
+        }
+    }
+}
+
+// Parses the DER structure without insisting on minimal encoding: lengths
+// must be consistent, but integers may be padded and trailing bytes after
+// the sequence are ignored.
+fn parse_der(data: &[u8]) -> Result<Signature, BlockchainError> {
+    if data.len() < 2 || data[0] != 0x30 {
+        return Err(BlockchainError::InvalidSignature("not a DER sequence"));
+    }
+    let length = data[1] as usize;
+    if length > data.len() - 2 {
+        return Err(BlockchainError::InvalidSignature("sequence longer than signature"));
+    }
+    let mut rest = &data[2..2 + length];
+    let r = parse_der_integer(&mut rest)?;
+    let s = parse_der_integer(&mut rest)?;
+
+    check_range(Signature::new(r, s))
+}
+
+// r and s have to be in the range 1 to n - 1, whatever the encoding.
+fn check_range(signature: Signature) -> Result<Signature, BlockchainError> {
+    let order = U256::from_be_bytes(CURVE_ORDER);
+    for value in &[signature.r, signature.s] {
+        if value.is_zero() {
+            return Err(BlockchainError::InvalidSignature("zero r or s"));
+        }
+        if *value >= order {
+            return Err(BlockchainError::InvalidSignature("r or s is not below the curve order"));
+        }
+    }
+
+    Ok(signature)
+}
+
+fn parse_der_integer(data: &mut &[u8]) -> Result<U256, BlockchainError> {
+    if data.len() < 2 || data[0] != 0x02 {
+        return Err(BlockchainError::InvalidSignature("not a DER integer"));
+    }
+    let length = data[1] as usize;
+    if length > data.len() - 2 {
+        return Err(BlockchainError::InvalidSignature("integer longer than signature"));
+    }
+    let mut bytes = &data[2..2 + length];
+    *data = &data[2 + length..];
+    while let Some((0, rest)) = bytes.split_first() {
+        bytes = rest;
+    }
+    if bytes.len() > 32 {
+        return Err(BlockchainError::InvalidSignature("integer out of range"));
+    }
+    let mut value = [0; 32];
+    value[32 - bytes.len()..].copy_from_slice(bytes);
+
+    Ok(U256::from_be_bytes(value))
+}
+
+// The minimal big-endian encoding of a non-negative DER integer.
+fn der_integer(value: &U256) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(31);
+    let mut integer = Vec::with_capacity(33);
+    if bytes[start] & 0x80 != 0 {
+        integer.push(0);
+    }
+    integer.extend_from_slice(&bytes[start..]);
+    integer
+}
+
+// BIP66 strict DER encoding of an ECDSA signature followed by a sighash byte.
+pub(crate) fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let r_length = sig[3] as usize;
+    if 5 + r_length >= sig.len() {
+        return false;
+    }
+    let s_length = sig[5 + r_length] as usize;
+    if r_length + s_length + 7 != sig.len() {
+        return false;
+    }
+
+    let integer_ok = |start: usize, length: usize| {
+        sig[start - 2] == 0x02 && length != 0 && sig[start] & 0x80 == 0 &&
+        !(length > 1 && sig[start] == 0x00 && sig[start + 1] & 0x80 == 0)
+    };
+    integer_ok(4, r_length) && integer_ok(r_length + 6, s_length)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::{from_hex, to_hex};
+
+    // The signature from the first bitcoin transaction, block 170.
+    const BLOCK_170_SIGNATURE: &str = "304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6\
+                                       c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831c\
+                                       c56cbbac4622082221a8768d1d0901";
+
+    #[test]
+    fn test_der_round_trip() {
+        let push = from_hex(BLOCK_170_SIGNATURE).unwrap();
+        let (signature, sighash_type) = Signature::from_script_push(&push, Strictness::LowS)
+            .unwrap();
+        assert_eq!(0x01, sighash_type);
+        assert_eq!(push, signature.to_script_push(0x01));
+        assert_eq!("4e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd41",
+                   to_hex(&signature.to_compact()[..32]));
+        assert_eq!(signature,
+                   Signature::from_compact(&signature.to_compact()).unwrap());
+    }
+
+    #[test]
+    fn test_strictness() {
+        let der = from_hex(BLOCK_170_SIGNATURE).unwrap();
+        let der = &der[..der.len() - 1];
+        let high_s = Signature::from_der(der, Strictness::Der).unwrap().normalize_s();
+        assert!(high_s.is_low_s());
+        let high_s = Signature::new(high_s.r(), U256::from_be_bytes(CURVE_ORDER) - high_s.s());
+        assert!(!high_s.is_low_s());
+        assert!(Signature::from_der(&high_s.to_der(), Strictness::Der).is_ok());
+        assert!(matches!(Signature::from_der(&high_s.to_der(), Strictness::LowS),
+                         Err(BlockchainError::InvalidSignature(_))));
+        assert!(high_s.normalize_s().is_low_s());
+
+        // An r padded with an unnecessary zero byte is only accepted laxly.
+        let mut padded = vec![0x30, der[1] + 1, 0x02, der[3] + 1, 0x00];
+        padded.extend_from_slice(&der[4..]);
+        let lax = Signature::from_der(&padded, Strictness::Lax).unwrap();
+        assert_eq!(der, lax.to_der().as_slice());
+        assert!(matches!(Signature::from_der(&padded, Strictness::Der),
+                         Err(BlockchainError::InvalidSignature(_))));
+
+        assert!(matches!(Signature::from_der(&[0x30, 0x06, 0x02, 0x01], Strictness::Lax),
+                         Err(BlockchainError::InvalidSignature(_))));
+        assert!(matches!(Signature::from_compact(&[0; 63]),
+                         Err(BlockchainError::InvalidLength { expected: 64, found: 63 })));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let order = U256::from_be_bytes(CURVE_ORDER);
+        assert!(matches!(Signature::from_compact(&[0xff; 64]),
+                         Err(BlockchainError::InvalidSignature(_))));
+        let mut compact = [0x01; 64];
+        compact[32..].copy_from_slice(&[0; 32]);
+        assert!(matches!(Signature::from_compact(&compact),
+                         Err(BlockchainError::InvalidSignature(_))));
+
+        // s equal to the curve order is rejected at every strictness level.
+        let at_order = Signature::new(U256::from(1u64), order).to_der();
+        let zero = Signature::new(U256::from(1u64), U256::from(0u64)).to_der();
+        for strictness in &[Strictness::Lax, Strictness::Der, Strictness::LowS] {
+            assert!(matches!(Signature::from_der(&at_order, *strictness),
+                             Err(BlockchainError::InvalidSignature(_))));
+            assert!(matches!(Signature::from_der(&zero, *strictness),
+                             Err(BlockchainError::InvalidSignature(_))));
+        }
+
+        let high = Signature::new(U256::from(1u64), order + U256::from(1u64));
+        assert_eq!(high, high.normalize_s());
+    }
+}
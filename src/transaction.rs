@@ -1,25 +1,71 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{self, Read, Write};
-use util::*;
-
-#[derive(Clone, Debug, PartialEq)]
+use byteorder::LittleEndian;
+use crate::amount::Amount;
+use crate::error::BlockchainError;
+use crate::hash::{Hash256, double_hash};
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::locktime::{LockTime, Sequence};
+use crate::prelude::*;
+use crate::script::Script;
+use crate::util::*;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+// Without std, transactions cache their hashes in a cell and are not Sync.
+#[cfg(not(feature = "std"))]
+use core::cell::OnceCell as OnceLock;
+
+const SEGWIT_MARKER: u8 = 0x00;
+pub(crate) const SEGWIT_FLAG: u8 = 0x01;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Outpoint {
-    hash: [u8; 32],
+    hash: Hash256,
     index: u32,
 }
 
+impl Outpoint {
+    pub fn new(txid: Hash256, vout: u32) -> Outpoint {
+        Outpoint {
+            hash: txid,
+            index: vout,
+        }
+    }
+
+    // The outpoint referenced by a coinbase input.
+    pub fn null() -> Outpoint {
+        Outpoint {
+            hash: Hash256::default(),
+            index: u32::MAX,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Outpoint::null()
+    }
+
+    pub fn txid(&self) -> &Hash256 {
+        &self.hash
+    }
+
+    pub fn vout(&self) -> u32 {
+        self.index
+    }
+}
+
 impl Serializable for Outpoint {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.write_all(&self.hash)?;
-        buffer.write_u32::<LittleEndian>(self.index)?;
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.hash.serialize_to(writer)?;
+        writer.write_u32::<LittleEndian>(self.index)?;
+
+        Ok(())
+    }
 
-        Ok(buffer)
+    fn serialized_size(&self) -> usize {
+        self.hash.serialized_size() + 4
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, io::Error> {
-        let mut hash: [u8; 32] = [0; 32];
-        reader.read_exact(&mut hash)?;
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        let hash = Hash256::deserialize_from(reader)?;
         let index = reader.read_u32::<LittleEndian>()?;
 
         Ok(Outpoint {
@@ -29,89 +75,188 @@ impl Serializable for Outpoint {
     }
 }
 
+// The witness stack of a segwit input.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Witness(#[cfg_attr(feature = "serde", serde(with = "crate::util::hex_list"))]
+                   Vec<Vec<u8>>);
+
+impl Witness {
+    pub fn new(items: Vec<Vec<u8>>) -> Witness {
+        Witness(items)
+    }
+
+    pub fn items(&self) -> &[Vec<u8>] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Serializable for Witness {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        VarInt(self.0.len() as u64).serialize_to(writer)?;
+        for item in &self.0 {
+            VarInt(item.len() as u64).serialize_to(writer)?;
+            writer.write_all(item.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        VarInt(self.0.len() as u64).serialized_size() +
+        self.0
+            .iter()
+            .map(|item| VarInt(item.len() as u64).serialized_size() + item.len())
+            .sum::<usize>()
+    }
+
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Witness::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        let count = VarInt::deserialize_from(reader)?;
+        let mut items: Vec<Vec<u8>> = Vec::new();
+        for _ in 0..count.0 {
+            items.push(read_bytes(reader, "witness item size", limits.max_script_size)?);
+        }
+
+        Ok(Witness(items))
+    }
+}
+
+// The witness is not part of an input's own serialization; transactions
+// write all witnesses together after the outputs.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Input {
     prev_hash: Outpoint,
-    txin_script: Vec<u8>,
-    sequence_no: u32,
+    txin_script: Script,
+    sequence_no: Sequence,
+    witness: Witness,
 }
 
 impl Input {
-    pub fn new(prev_hash: &[u8; 32], prev_seq: u32, script: &[u8], sequence_no: u32) -> Input {
+    pub fn new(prev_hash: Outpoint, script: Script, sequence_no: Sequence) -> Input {
         Input {
-            prev_hash: Outpoint {
-                hash: *prev_hash,
-                index: prev_seq,
-            },
-            txin_script: script.to_vec(),
+            prev_hash: prev_hash,
+            txin_script: script,
             sequence_no: sequence_no,
+            witness: Witness::default(),
         }
     }
+
+    pub fn with_witness(mut self, witness: Witness) -> Input {
+        self.witness = witness;
+        self
+    }
+
+    pub fn previous_output(&self) -> &Outpoint {
+        &self.prev_hash
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.txin_script
+    }
+
+    pub fn sequence(&self) -> Sequence {
+        self.sequence_no
+    }
+
+    pub fn set_sequence(&mut self, sequence_no: Sequence) {
+        self.sequence_no = sequence_no;
+    }
+
+    pub fn witness(&self) -> &Witness {
+        &self.witness
+    }
 }
 
 impl Serializable for Input {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.write_all(&self.prev_hash.serialize()?)?;
-        buffer
-            .write_all(VarInt(self.txin_script.len() as u64)
-                           .serialize()?
-                           .as_slice())?;
-        buffer.write_all(self.txin_script.as_slice())?;
-        buffer.write_u32::<LittleEndian>(self.sequence_no)?;
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.prev_hash.serialize_to(writer)?;
+        self.txin_script.serialize_to(writer)?;
+        self.sequence_no.serialize_to(writer)?;
+
+        Ok(())
+    }
 
-        Ok(buffer)
+    fn serialized_size(&self) -> usize {
+        self.prev_hash.serialized_size() + self.txin_script.serialized_size() +
+        self.sequence_no.serialized_size()
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, io::Error> {
-        let prev_hash = Outpoint::deserialize(reader)?;
-        let txin_script_length = VarInt::deserialize(reader)?;
-        println!("txin script length = {}", txin_script_length.0);
-        let mut txin_script = vec![0; txin_script_length.0 as usize];
-        reader.read_exact(txin_script.as_mut_slice())?;
-        let sequence_no = reader.read_u32::<LittleEndian>()?;
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Input::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        let prev_hash = Outpoint::deserialize_from(reader)?;
+        let txin_script = Script::deserialize_with_limits(reader, limits)?;
+        let sequence_no = Sequence::deserialize_from(reader)?;
 
         Ok(Input {
                prev_hash: prev_hash,
                txin_script: txin_script,
                sequence_no: sequence_no,
+               witness: Witness::default(),
            })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Output {
-    value: u64,
-    txout_script: Vec<u8>,
+    value: Amount,
+    txout_script: Script,
 }
 
 impl Output {
-    pub fn new(value: u64, script: &[u8]) -> Output {
+    pub fn new(value: Amount, script: Script) -> Output {
         Output {
             value: value,
-            txout_script: script.to_vec(),
+            txout_script: script,
         }
     }
+
+    pub fn value(&self) -> Amount {
+        self.value
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.txout_script
+    }
 }
 
 impl Serializable for Output {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.write_u64::<LittleEndian>(self.value)?;
-        buffer
-            .write_all(VarInt(self.txout_script.len() as u64)
-                           .serialize()?
-                           .as_slice())?;
-        buffer.write_all(self.txout_script.as_slice())?;
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.value.serialize_to(writer)?;
+        self.txout_script.serialize_to(writer)?;
+
+        Ok(())
+    }
 
-        Ok(buffer)
+    fn serialized_size(&self) -> usize {
+        self.value.serialized_size() + self.txout_script.serialized_size()
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, io::Error> {
-        let value = reader.read_u64::<LittleEndian>()?;
-        let txout_script_length = VarInt::deserialize(reader)?;
-        let mut txout_script = vec![0; txout_script_length.0 as usize];
-        reader.read_exact(txout_script.as_mut_slice())?;
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Output::deserialize_with_limits(reader, &DeserializeLimits::default())
+    }
+
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        let value = Amount::deserialize_from(reader)?;
+        let txout_script = Script::deserialize_with_limits(reader, limits)?;
         Ok(Output {
                value: value,
                txout_script: txout_script,
@@ -119,75 +264,383 @@ impl Serializable for Output {
     }
 }
 
-#[derive(Debug, PartialEq)]
+// A lazily computed hash. It's derived from the rest of the transaction, so
+// it's ignored when comparing transactions.
+#[derive(Clone, Debug, Default)]
+struct CachedHash(OnceLock<Hash256>);
+
+impl CachedHash {
+    fn get_or_compute<F>(&self, compute: F) -> Result<Hash256, BlockchainError>
+        where F: FnOnce() -> Result<Hash256, BlockchainError>
+    {
+        if let Some(hash) = self.0.get() {
+            return Ok(*hash);
+        }
+        let hash = compute()?;
+        let _ = self.0.set(hash);
+
+        Ok(hash)
+    }
+}
+
+impl PartialEq for CachedHash {
+    fn eq(&self, _: &CachedHash) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Transaction {
     version: u32,
     inputs: Vec<Input>,
     outputs: Vec<Output>,
-    lock_time: u32,
+    lock_time: LockTime,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    txid: CachedHash,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    wtxid: CachedHash,
 }
 
 impl Transaction {
-    pub fn new(version: u32, inputs: &[Input], outputs: &[Output], lock_time: u32) -> Transaction {
+    pub fn new(version: u32,
+               inputs: &[Input],
+               outputs: &[Output],
+               lock_time: LockTime)
+               -> Transaction {
         Transaction {
             version: version,
             inputs: inputs.to_vec(),
             outputs: outputs.to_vec(),
             lock_time: lock_time,
+            txid: CachedHash::default(),
+            wtxid: CachedHash::default(),
         }
     }
-}
 
-impl Serializable for Transaction {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.write_u32::<LittleEndian>(self.version)?;
-        buffer
-            .write_all(VarInt(self.inputs.len() as u64).serialize()?.as_slice())?;
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    pub fn lock_time(&self) -> LockTime {
+        self.lock_time
+    }
+
+    // The total value of the outputs, checked against the money range.
+    pub fn output_total(&self) -> Result<Amount, BlockchainError> {
+        Amount::checked_sum(self.outputs.iter().map(|output| output.value))
+    }
+
+    // The fee paid, given the values of the outputs spent by each input.
+    pub fn fee(&self, input_values: &[Amount]) -> Result<Amount, BlockchainError> {
+        if input_values.len() != self.inputs.len() {
+            return Err(BlockchainError::InvalidLength {
+                           expected: self.inputs.len(),
+                           found: input_values.len(),
+                       });
+        }
+        let inputs = Amount::checked_sum(input_values.iter().cloned())?;
+        inputs
+            .checked_sub(self.output_total()?)
+            .ok_or(BlockchainError::InvalidTransaction("outputs exceed inputs"))
+    }
+
+    // The mutators below change the serialization, so they also drop the
+    // cached hashes.
+
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+        self.clear_cached_hashes();
+    }
+
+    pub fn set_lock_time(&mut self, lock_time: LockTime) {
+        self.lock_time = lock_time;
+        self.clear_cached_hashes();
+    }
+
+    pub fn push_input(&mut self, input: Input) {
+        self.inputs.push(input);
+        self.clear_cached_hashes();
+    }
+
+    pub fn push_output(&mut self, output: Output) {
+        self.outputs.push(output);
+        self.clear_cached_hashes();
+    }
+
+    // Panics if there is no input at `index`.
+    pub fn set_witness(&mut self, index: usize, witness: Witness) {
+        self.inputs[index].witness = witness;
+        self.clear_cached_hashes();
+    }
+
+    fn clear_cached_hashes(&mut self) {
+        self.txid = CachedHash::default();
+        self.wtxid = CachedHash::default();
+    }
+
+    // Size of the serialization without witness data.
+    pub fn stripped_size(&self) -> usize {
+        if !self.has_witness() {
+            return self.serialized_size();
+        }
+        self.serialized_size() - 2 -
+        self.inputs
+            .iter()
+            .map(|input| input.witness.serialized_size())
+            .sum::<usize>()
+    }
+
+    // BIP141 weight: witness bytes count once, everything else four times.
+    pub fn weight(&self) -> usize {
+        self.stripped_size() * 3 + self.serialized_size()
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    // Hash of the serialization without witness data. This is what outpoints
+    // refer to. Computed once and cached.
+    pub fn txid(&self) -> Result<Hash256, BlockchainError> {
+        self.txid
+            .get_or_compute(|| {
+                                let mut buffer: Vec<u8> = Vec::new();
+                                self.serialize_with(&mut buffer, false)?;
+                                Ok(double_hash(buffer.as_slice()))
+                            })
+    }
+
+    // Hash of the full serialization, including witness data if present.
+    // Computed once and cached.
+    pub fn wtxid(&self) -> Result<Hash256, BlockchainError> {
+        self.wtxid
+            .get_or_compute(|| Ok(double_hash(self.serialize()?.as_slice())))
+    }
+
+    // BIP144: transactions with witness data insert a zero marker and a flag
+    // byte after the version, and the witnesses after the outputs.
+    fn serialize_with<W: Write>(&self,
+                                writer: &mut W,
+                                include_witness: bool)
+                                -> Result<(), BlockchainError> {
+        let witness = include_witness && self.has_witness();
+        writer.write_u32::<LittleEndian>(self.version)?;
+        if witness {
+            writer.write_u8(SEGWIT_MARKER)?;
+            writer.write_u8(SEGWIT_FLAG)?;
+        }
+        VarInt(self.inputs.len() as u64).serialize_to(writer)?;
         for input in &self.inputs {
-            buffer.write_all(input.serialize()?.as_slice())?;
+            input.serialize_to(writer)?;
         }
-        buffer
-            .write_all(VarInt(self.outputs.len() as u64)
-                           .serialize()?
-                           .as_slice())?;
+        VarInt(self.outputs.len() as u64).serialize_to(writer)?;
         for output in &self.outputs {
-            buffer.write_all(output.serialize()?.as_slice())?;
+            output.serialize_to(writer)?;
+        }
+        if witness {
+            for input in &self.inputs {
+                input.witness.serialize_to(writer)?;
+            }
+        }
+        self.lock_time.serialize_to(writer)?;
+
+        Ok(())
+    }
+
+    // A transaction is final once its lock time has passed, or if every
+    // input opts out of lock time enforcement.
+    pub fn is_final(&self, height: u32, time: u32) -> bool {
+        self.lock_time.is_satisfied_by(height, time) ||
+        self.inputs.iter().all(|input| input.sequence_no.is_final())
+    }
+}
+
+impl Serializable for Transaction {
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
+        self.serialize_with(writer, true)
+    }
+
+    fn serialized_size(&self) -> usize {
+        let mut size = 4 + VarInt(self.inputs.len() as u64).serialized_size() +
+                       self.inputs.iter().map(|input| input.serialized_size()).sum::<usize>() +
+                       VarInt(self.outputs.len() as u64).serialized_size() +
+                       self.outputs
+                           .iter()
+                           .map(|output| output.serialized_size())
+                           .sum::<usize>() + self.lock_time.serialized_size();
+        if self.has_witness() {
+            size += 2 +
+                    self.inputs
+                        .iter()
+                        .map(|input| input.witness.serialized_size())
+                        .sum::<usize>();
         }
-        buffer.write_u32::<LittleEndian>(self.lock_time)?;
+        size
+    }
+
+    // BIP144 reads an empty input count as the marker of an extended
+    // transaction, so a transaction without inputs doesn't read back.
+    fn round_trips(&self) -> bool {
+        !self.inputs.is_empty()
+    }
+
+    // Blocks commit to transactions without their witnesses.
+    fn merkle_leaf(&self) -> Result<Hash256, BlockchainError> {
+        self.txid()
+    }
 
-        Ok(buffer)
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        Transaction::deserialize_with_limits(reader, &DeserializeLimits::default())
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, io::Error> {
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
         let version = reader.read_u32::<LittleEndian>()?;
-        let input_length = VarInt::deserialize(reader)?;
+        let mut input_length = VarInt::deserialize_from(reader)?;
+        // An empty input list can't be a valid legacy transaction, so it
+        // marks the extended format instead.
+        let mut witness = false;
+        if input_length.0 == SEGWIT_MARKER as u64 {
+            let flag = reader.read_u8()?;
+            if flag != SEGWIT_FLAG {
+                return Err(BlockchainError::InvalidWitness("unknown segwit flag"));
+            }
+            witness = true;
+            input_length = VarInt::deserialize_from(reader)?;
+        }
         let mut inputs: Vec<Input> = Vec::new();
         for _ in 0..input_length.0 {
-            inputs.push(Input::deserialize(reader)?);
+            inputs.push(Input::deserialize_with_limits(reader, limits)?);
         }
-        let output_length = VarInt::deserialize(reader)?;
+        let output_length = VarInt::deserialize_from(reader)?;
         let mut outputs: Vec<Output> = Vec::new();
         for _ in 0..output_length.0 {
-            outputs.push(Output::deserialize(reader)?);
+            outputs.push(Output::deserialize_with_limits(reader, limits)?);
+        }
+        if witness {
+            for input in &mut inputs {
+                input.witness = Witness::deserialize_with_limits(reader, limits)?;
+            }
+            // Otherwise the transaction would have two encodings.
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(BlockchainError::InvalidWitness("superfluous witness record"));
+            }
         }
-        let lock_time = reader.read_u32::<LittleEndian>()?;
+        let lock_time = LockTime::deserialize_from(reader)?;
 
         Ok(Transaction {
                version: version,
                inputs: inputs,
                outputs: outputs,
                lock_time: lock_time,
+               txid: CachedHash::default(),
+               wtxid: CachedHash::default(),
            })
     }
 }
 
+// Assembles a transaction one input and output at a time. Inputs and
+// outputs are checked when the transaction is built.
+#[derive(Clone, Debug)]
+pub struct TransactionBuilder {
+    version: u32,
+    inputs: Vec<Input>,
+    outputs: Vec<Output>,
+    lock_time: LockTime,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> TransactionBuilder {
+        TransactionBuilder {
+            version: 2,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            lock_time: LockTime::ZERO,
+        }
+    }
+
+    pub fn version(mut self, version: u32) -> TransactionBuilder {
+        self.version = version;
+        self
+    }
+
+    pub fn add_input(mut self,
+                     outpoint: Outpoint,
+                     script: Script,
+                     sequence_no: Sequence)
+                     -> TransactionBuilder {
+        self.inputs.push(Input::new(outpoint, script, sequence_no));
+        self
+    }
+
+    pub fn add_output(mut self, amount: Amount, script: Script) -> TransactionBuilder {
+        self.outputs.push(Output::new(amount, script));
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: LockTime) -> TransactionBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    pub fn build(self) -> Result<Transaction, BlockchainError> {
+        if self.inputs.is_empty() {
+            return Err(BlockchainError::InvalidTransaction("no inputs"));
+        }
+        if self.outputs.is_empty() {
+            return Err(BlockchainError::InvalidTransaction("no outputs"));
+        }
+        let transaction =
+            Transaction::new(self.version, &self.inputs, &self.outputs, self.lock_time);
+        transaction.output_total()?;
+
+        Ok(transaction)
+    }
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> TransactionBuilder {
+        TransactionBuilder::new()
+    }
+}
+
+#[cfg(test)]
 mod test {
     use super::*;
+    use crate::hash::Hash256;
+    use crate::locktime::{LockTime, Sequence};
+    use crate::script::Script;
+
+    #[test]
+    fn test_outpoint() {
+        let null = Outpoint::null();
+        assert!(null.is_null());
+        assert!(!Outpoint::new(Hash256::default(), 0).is_null());
+        assert!(Outpoint::new(Hash256::from([0; 32]), 1) <
+                Outpoint::new(Hash256::from([1; 32]), 0));
+
+        let serialized = null.serialize().unwrap();
+        assert_eq!(36, serialized.len());
+        assert_eq!(null, Outpoint::deserialize(&serialized).unwrap());
+    }
 
     #[test]
     fn test_input_serialization() {
-        let mut serialized =
+        let serialized =
             vec![0x6D, 0xBD, 0xDB, 0x08, 0x5B, 0x1D, 0x8A, 0xF7, 0x51, 0x84, 0xF0, 0xBC, 0x01,
                  0xFA, 0xD5, 0x8D, 0x12, 0x66, 0xE9, 0xB6, 0x3B, 0x50, 0x88, 0x19, 0x90, 0xE4,
                  0xB4, 0x0D, 0x6A, 0xEE, 0x36, 0x29, 0x00, 0x00, 0x00, 0x00, 0x8B, 0x48, 0x30,
@@ -220,10 +673,13 @@ mod test {
                           0xD6, 0xD4, 0x51, 0x1A, 0xDA, 0x8F, 0x14, 0x04, 0x2F, 0x46, 0x61, 0x4A,
                           0x4C, 0x70, 0xC0, 0xF1, 0x4B, 0xEF, 0xF5];
 
-        let input = Input::new(&prev_hash, 0, &script, 4294967295);
+        let input = Input::new(Outpoint::new(Hash256::from(prev_hash), 0),
+                               Script::from(script),
+                               Sequence::MAX);
         assert_eq!(serialized, input.serialize().unwrap());
+        assert_eq!(serialized.len(), input.serialized_size());
         assert_eq!(input,
-                   Input::deserialize(&mut serialized.as_slice()).unwrap());
+                   Input::deserialize(&serialized).unwrap());
     }
 
     #[test]
@@ -237,11 +693,47 @@ mod test {
                           0x7A, 0xBA, 0xD5, 0x12, 0xA9, 0xD9, 0xEA, 0x1A, 0xFB, 0x22, 0x5E, 0x88,
                           0xAC];
 
-        let output = Output::new(5000000, &script);
+        let output = Output::new(Amount::from_sat(5000000), Script::from(script));
 
         assert_eq!(serialized, output.serialize().unwrap());
+        assert_eq!(serialized.len(), output.serialized_size());
+        assert_eq!(output,
+                   Output::deserialize(&serialized).unwrap());
+    }
+
+    #[test]
+    fn test_oversized_script_rejected() {
+        // The length prefix claims an 8 GB script.
+        let serialized = vec![0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00,
+                              0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x6a];
+        assert!(matches!(Output::deserialize(&serialized),
+                         Err(BlockchainError::LimitExceeded { what: "script size", .. })));
+
+        let mut limits = DeserializeLimits {
+            max_script_size: 1,
+            ..DeserializeLimits::default()
+        };
+        let output = Output::new(Amount::from_sat(1), Script::from(vec![0x6a, 0x00]));
+        let serialized = output.serialize().unwrap();
+        assert!(Output::deserialize_with_limits(&mut serialized.as_slice(), &limits).is_err());
+        limits.max_script_size = 2;
         assert_eq!(output,
-                   Output::deserialize(&mut serialized.as_slice()).unwrap());
+                   Output::deserialize_with_limits(&mut serialized.as_slice(), &limits).unwrap());
+    }
+
+    #[test]
+    fn test_streamed_serialization() {
+        let first = Output::new(Amount::from_sat(1), Script::from(vec![0x51]));
+        let second = Output::new(Amount::from_sat(2), Script::from(vec![0x52, 0x53]));
+
+        let mut buffer: Vec<u8> = Vec::new();
+        first.serialize_to(&mut buffer).unwrap();
+        second.serialize_to(&mut buffer).unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(first, Output::deserialize_from(&mut reader).unwrap());
+        assert_eq!(second, Output::deserialize_from(&mut reader).unwrap());
+        assert!(reader.is_empty());
     }
 
     #[test]
@@ -293,16 +785,161 @@ mod test {
                                    0x84, 0xCF, 0xAB, 0x12, 0x48, 0x5E, 0xFD, 0xA0, 0xB7, 0x8B,
                                    0x4E, 0xCC, 0x52, 0x88, 0xAC];
 
-        let input = Input::new(&prev_hash, 0, &input_script, 4294967295);
+        let input = Input::new(Outpoint::new(Hash256::from(prev_hash), 0),
+                               Script::from(input_script),
+                               Sequence::MAX);
 
-        let output_1 = Output::new(5000000, &output_script_1);
-        let output_2 = Output::new(3354000000, &output_script_2);
+        let output_1 = Output::new(Amount::from_sat(5000000), Script::from(output_script_1));
+        let output_2 = Output::new(Amount::from_sat(3354000000), Script::from(output_script_2));
 
-        let transaction = Transaction::new(1, &[input], &[output_1, output_2], 0);
+        let transaction =
+            Transaction::new(1, &[input], &[output_1, output_2], LockTime::ZERO);
 
-        let mine = transaction.serialize().unwrap();
+        assert_eq!(serialized, transaction.serialize().unwrap());
+        assert_eq!(serialized.len(), transaction.serialized_size());
+        assert_eq!(transaction, Transaction::deserialize(&serialized).unwrap());
+    }
 
+    #[test]
+    fn test_segwit_transaction() {
+        // Signed native P2WPKH example from BIP143.
+        let hex = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad96\
+                   9f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d11\
+                   4c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618e\
+                   f3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec6\
+                   8a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a\
+                   76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa81\
+                   5988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7\
+                   f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee\
+                   0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+        let serialized = from_hex(hex).unwrap();
+
+        let transaction = Transaction::deserialize(&serialized).unwrap();
+        assert!(transaction.has_witness());
+        assert!(transaction.inputs[0].witness().is_empty());
+        assert_eq!(2, transaction.inputs[1].witness().items().len());
         assert_eq!(serialized, transaction.serialize().unwrap());
-        assert_eq!(transaction, Transaction::deserialize(&mut serialized.as_slice()).unwrap());
+        assert_eq!(serialized.len(), transaction.serialized_size());
+
+        assert_eq!("e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609",
+                   transaction.txid().unwrap().to_string());
+        // Served from the cache the second time, and still equal to an
+        // uncached copy.
+        assert_eq!("e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609",
+                   transaction.txid().unwrap().to_string());
+        assert_eq!(Transaction::deserialize(&serialized).unwrap(), transaction);
+        assert_eq!("c36c38370907df2324d9ce9d149d191192f338b37665a82e78e76a12c909b762",
+                   transaction.wtxid().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_transaction_builder() {
+        let transaction = TransactionBuilder::new()
+            .version(1)
+            .add_input(Outpoint::new(Hash256::from([1; 32]), 2),
+                       Script::from(vec![0x51]),
+                       Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .add_output(Amount::from_sat(1000), Script::from(vec![0x6a]))
+            .add_output(Amount::from_sat(2000), Script::new())
+            .lock_time(LockTime::from_height(500000).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(1, transaction.version());
+        assert_eq!(2, transaction.inputs()[0].previous_output().vout());
+        assert_eq!(Amount::from_sat(2000), transaction.outputs()[1].value());
+        assert_eq!(LockTime::from_height(500000).unwrap(), transaction.lock_time());
+
+        let builder = TransactionBuilder::new()
+            .add_input(Outpoint::null(), Script::new(), Sequence::MAX);
+        assert!(matches!(builder.clone().build(),
+                         Err(BlockchainError::InvalidTransaction("no outputs"))));
+        let no_inputs = TransactionBuilder::new().add_output(Amount::from_sat(1), Script::new());
+        assert!(matches!(no_inputs.build(),
+                         Err(BlockchainError::InvalidTransaction("no inputs"))));
+        assert!(builder.clone().add_output(Amount::MAX_MONEY, Script::new()).build().is_ok());
+        assert!(matches!(builder
+                             .add_output(Amount::MAX_MONEY, Script::new())
+                             .add_output(Amount::from_sat(1), Script::new())
+                             .build(),
+                         Err(BlockchainError::InvalidAmount)));
+    }
+
+    #[test]
+    fn test_fee() {
+        let transaction = TransactionBuilder::new()
+            .add_input(Outpoint::null(), Script::new(), Sequence::MAX)
+            .add_input(Outpoint::null(), Script::new(), Sequence::MAX)
+            .add_output(Amount::from_sat(700), Script::new())
+            .build()
+            .unwrap();
+        assert_eq!(Amount::from_sat(700), transaction.output_total().unwrap());
+        assert_eq!(Amount::from_sat(300),
+                   transaction
+                       .fee(&[Amount::from_sat(600), Amount::from_sat(400)])
+                       .unwrap());
+        assert!(transaction.fee(&[Amount::from_sat(1000)]).is_err());
+        assert!(matches!(transaction.fee(&[Amount::from_sat(600), Amount::from_sat(99)]),
+                         Err(BlockchainError::InvalidTransaction(_))));
+    }
+
+    #[test]
+    fn test_mutators_clear_cached_hashes() {
+        let input = Input::new(Outpoint::null(), Script::new(), Sequence::MAX);
+        let mut transaction = Transaction::new(1, &[input], &[], LockTime::ZERO);
+        let original = transaction.txid().unwrap();
+
+        transaction.push_output(Output::new(Amount::from_sat(5), Script::from(vec![0x51])));
+        assert_eq!(1, transaction.outputs()[0].script().len());
+        let with_output = transaction.txid().unwrap();
+        assert!(original != with_output);
+
+        transaction.set_lock_time(LockTime::from_height(100).unwrap());
+        assert!(with_output != transaction.txid().unwrap());
+
+        let wtxid = transaction.wtxid().unwrap();
+        transaction.set_witness(0, Witness::new(vec![vec![0x01]]));
+        assert!(wtxid != transaction.wtxid().unwrap());
+        let serialized = transaction.serialize().unwrap();
+        assert_eq!(transaction, Transaction::deserialize(&serialized).unwrap());
+    }
+
+    #[test]
+    fn test_superfluous_witness_rejected() {
+        let input = Input::new(Outpoint::null(), Script::new(), Sequence::MAX);
+        let output = Output::new(Amount::ZERO, Script::new());
+        let transaction = Transaction::new(1, &[input], &[output], LockTime::ZERO);
+        let legacy = transaction.serialize().unwrap();
+        assert_eq!(transaction.txid().unwrap(), transaction.wtxid().unwrap());
+
+        let mut extended = legacy[..4].to_vec();
+        extended.extend_from_slice(&[SEGWIT_MARKER, SEGWIT_FLAG]);
+        extended.extend_from_slice(&legacy[4..legacy.len() - 4]);
+        extended.push(0);
+        extended.extend_from_slice(&legacy[legacy.len() - 4..]);
+        assert!(matches!(Transaction::deserialize(&extended),
+                         Err(BlockchainError::InvalidWitness(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_round_trip() {
+        let input = Input::new(Outpoint::new(Hash256::from([1; 32]), 3),
+                               Script::from(vec![0x51, 0xab]),
+                               Sequence::MAX)
+                .with_witness(Witness::new(vec![vec![0xde, 0xad], vec![]]));
+        let output = Output::new(Amount::from_sat(1000), Script::from(vec![0x6a]));
+        let transaction =
+            Transaction::new(2, &[input], &[output], LockTime::from_time(1500000000).unwrap());
+
+        let json = ::serde_json::to_value(&transaction).unwrap();
+        let input = &json["inputs"][0];
+        assert_eq!("0101010101010101010101010101010101010101010101010101010101010101",
+                   input["prev_hash"]["hash"]);
+        assert_eq!("51ab", input["txin_script"]);
+        assert_eq!(::serde_json::json!(["dead", ""]), input["witness"]);
+        assert_eq!(1500000000, json["lock_time"]);
+
+        let parsed: Transaction = ::serde_json::from_value(json).unwrap();
+        assert_eq!(transaction, parsed);
     }
 }
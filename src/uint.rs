@@ -0,0 +1,326 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::{Add, Div, Mul, Not, Rem, Shl, Shr, Sub};
+
+// An unsigned 256-bit integer, used for proof-of-work targets and chain
+// work. Arithmetic operators panic on overflow and division by zero, like
+// the built-in integer types in debug builds; use the checked methods where
+// the operands come from untrusted input.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0, 0, 0, 0]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub fn from_u64(value: u64) -> U256 {
+        U256([value, 0, 0, 0])
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> U256 {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - 8 * (i + 1);
+            let mut value = 0u64;
+            for byte in &bytes[start..start + 8] {
+                value = (value << 8) | *byte as u64;
+            }
+            *limb = value;
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 32 - 8 * (i + 1);
+            for j in 0..8 {
+                bytes[start + j] = (limb >> (56 - 8 * j)) as u8;
+            }
+        }
+        bytes
+    }
+
+    // Hashes are little-endian numbers.
+    pub fn from_le_bytes(bytes: [u8; 32]) -> U256 {
+        let mut reversed = bytes;
+        reversed.reverse();
+        U256::from_be_bytes(reversed)
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut bytes = self.to_be_bytes();
+        bytes.reverse();
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == U256::ZERO
+    }
+
+    // The low 64 bits, discarding the rest.
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    // The number of significant bits.
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + 64 - self.0[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, overflow1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, overflow2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = overflow1 || overflow2;
+        }
+        if carry {
+            return None;
+        }
+        Some(U256(result))
+    }
+
+    pub fn checked_sub(self, other: U256) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (difference, overflow1) = self.0[i].overflowing_sub(other.0[i]);
+            let (difference, overflow2) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = overflow1 || overflow2;
+        }
+        if borrow {
+            return None;
+        }
+        Some(U256(result))
+    }
+
+    pub fn checked_mul(self, other: U256) -> Option<U256> {
+        let mut result = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let product = self.0[i] as u128 * other.0[j] as u128 + result[i + j] as u128 +
+                              carry;
+                result[i + j] = product as u64;
+                carry = product >> 64;
+            }
+            result[i + 4] = carry as u64;
+        }
+        if result[4..].iter().any(|limb| *limb != 0) {
+            return None;
+        }
+        Some(U256([result[0], result[1], result[2], result[3]]))
+    }
+
+    // Quotient and remainder, or None when dividing by zero.
+    pub fn checked_div_rem(self, divisor: U256) -> Option<(U256, U256)> {
+        if divisor.is_zero() {
+            return None;
+        }
+        if self < divisor {
+            return Some((U256::ZERO, self));
+        }
+
+        // Shift-and-subtract long division, one bit of quotient at a time.
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for bit in (0..self.bits()).rev() {
+            remainder = remainder << 1;
+            if self.bit(bit) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= divisor {
+                remainder = remainder - divisor;
+                quotient.0[bit as usize / 64] |= 1 << (bit % 64);
+            }
+        }
+        Some((quotient, remainder))
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        self.0[index as usize / 64] & (1 << (index % 64)) != 0
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> U256 {
+        U256::from_u64(value)
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &U256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &U256) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        self.checked_add(other).expect("U256 addition overflowed")
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, other: U256) -> U256 {
+        self.checked_sub(other).expect("U256 subtraction overflowed")
+    }
+}
+
+impl Mul for U256 {
+    type Output = U256;
+
+    fn mul(self, other: U256) -> U256 {
+        self.checked_mul(other).expect("U256 multiplication overflowed")
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    fn div(self, other: U256) -> U256 {
+        self.checked_div_rem(other).expect("U256 division by zero").0
+    }
+}
+
+impl Rem for U256 {
+    type Output = U256;
+
+    fn rem(self, other: U256) -> U256 {
+        self.checked_div_rem(other).expect("U256 division by zero").1
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256([!self.0[0], !self.0[1], !self.0[2], !self.0[3]])
+    }
+}
+
+// Shifts drop bits shifted out of either end, and shifting by 256 or more
+// gives zero.
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        for (i, limb) in result.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        for (i, limb) in result.iter_mut().take(4usize.saturating_sub(limbs)).enumerate() {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl fmt::LowerHex for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.to_be_bytes().iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:x}", self)
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "U256({:x})", self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_byte_order() {
+        let mut bytes = [0; 32];
+        bytes[31] = 0x01;
+        bytes[0] = 0x80;
+        let value = U256::from_be_bytes(bytes);
+        assert_eq!(256, value.bits());
+        assert_eq!(1, value.low_u64());
+        assert_eq!(bytes, value.to_be_bytes());
+        let mut reversed = bytes;
+        reversed.reverse();
+        assert_eq!(value, U256::from_le_bytes(reversed));
+        assert_eq!(reversed, value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = U256::from(u64::MAX);
+        let sum = a + U256::ONE;
+        assert_eq!(U256::ONE << 64, sum);
+        assert_eq!(a, sum - U256::ONE);
+        assert_eq!(None, U256::MAX.checked_add(U256::ONE));
+        assert_eq!(None, U256::ZERO.checked_sub(U256::ONE));
+
+        let product = a * a;
+        assert_eq!((U256::ONE << 128) - (U256::ONE << 65) + U256::ONE, product);
+        assert_eq!(None, (U256::ONE << 200).checked_mul(U256::ONE << 56));
+
+        assert_eq!(a, product / a);
+        assert_eq!(U256::ZERO, product % a);
+        assert_eq!(U256::from(7), (product + U256::from(7)) % a);
+        assert_eq!(None, a.checked_div_rem(U256::ZERO));
+
+        assert_eq!(U256::ZERO, U256::MAX << 256);
+        assert_eq!(U256::ONE, U256::MAX >> 255);
+        assert_eq!(U256::from(0x1234) << 100 >> 100, U256::from(0x1234));
+        assert!(U256::ONE << 64 > U256::from(u64::MAX));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("00000000000000000000000000000000000000000000000000000000000100ff",
+                   U256::from(0x100ff).to_string());
+    }
+}
@@ -1,94 +1,395 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use ring;
-use std;
-use std::io::{self, Read};
+use byteorder::LittleEndian;
+use crate::error::BlockchainError;
+use crate::hash::*;
+use crate::io::{Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::prelude::*;
 
 pub trait Serializable: Sized {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>;
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError>;
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, io::Error>;
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError>;
+
+    // Deserializes with explicit bounds on attacker-controlled sizes. Types
+    // that read length prefixes override this, and their deserialize_from
+    // uses the default limits.
+    fn deserialize_with_limits<R: Read>(reader: &mut R,
+                                        _limits: &DeserializeLimits)
+                                        -> Result<Self, BlockchainError> {
+        Self::deserialize_from(reader)
+    }
+
+    // The exact number of bytes serialize_to will write.
+    fn serialized_size(&self) -> usize;
+
+    fn serialize(&self) -> Result<Vec<u8>, BlockchainError> {
+        let mut buffer: Vec<u8> = Vec::new();
+        self.serialize_to(&mut buffer)?;
+        #[cfg(feature = "serialization-audit")]
+        if self.round_trips() {
+            audit_serialization::<Self>(&buffer);
+        }
+
+        Ok(buffer)
+    }
+
+    // Deserializes a value that must span all of `data`.
+    fn deserialize(data: &[u8]) -> Result<Self, BlockchainError> {
+        let (value, consumed) = Self::deserialize_partial(data)?;
+        if consumed != data.len() {
+            return Err(BlockchainError::TrailingData(data.len() - consumed));
+        }
+
+        Ok(value)
+    }
+
+    // Deserializes a value from the start of `data`, returning it along with
+    // the number of bytes it occupied.
+    fn deserialize_partial(data: &[u8]) -> Result<(Self, usize), BlockchainError> {
+        let mut reader = data;
+        let value = Self::deserialize_from(&mut reader)?;
+
+        Ok((value, data.len() - reader.len()))
+    }
+
+    // Whether the serialized form reads back as this value. The encoding of
+    // a few values is ambiguous, and the serialization audit skips them.
+    fn round_trips(&self) -> bool {
+        true
+    }
+
+    // The hash this value contributes as a leaf of a block's merkle tree.
+    fn merkle_leaf(&self) -> Result<Hash256, BlockchainError> {
+        Ok(double_hash(self.serialize()?.as_slice()))
+    }
+
+    fn to_hex(&self) -> Result<String, BlockchainError> {
+        Ok(to_hex(self.serialize()?.as_slice()))
+    }
+
+    // Deserializes a value from a hex string, such as a raw transaction
+    // copied from a block explorer. The string must hold exactly one value.
+    fn from_hex(hex: &str) -> Result<Self, BlockchainError> {
+        Self::deserialize(from_hex(hex.trim())?.as_slice())
+    }
+}
+
+// Panics unless `bytes` deserializes to a value that serializes back to the
+// same bytes. The serialization-audit feature runs this on the output of
+// every serialize() call, other than for values whose round_trips() is
+// false, to catch encodings that don't round-trip.
+#[cfg(feature = "serialization-audit")]
+fn audit_serialization<T: Serializable>(bytes: &[u8]) {
+    let name = core::any::type_name::<T>();
+    let value = match T::deserialize(bytes) {
+        Ok(value) => value,
+        Err(error) => panic!("serialized {} doesn't deserialize: {}", name, error),
+    };
+    let mut again: Vec<u8> = Vec::new();
+    value.serialize_to(&mut again).unwrap();
+    assert_eq!(bytes, again.as_slice(), "serialized {} isn't stable", name);
+    assert_eq!(bytes.len(), value.serialized_size(), "wrong serialized size of {}", name);
 }
 
-pub fn single_hash(data: &[u8]) -> Result<Vec<u8>, io::Error> {
-    let digest = ring::digest::digest(&ring::digest::SHA256, data);
-    let mut buffer: Vec<u8> = Vec::new();
-    digest.as_ref().read_to_end(&mut buffer)?;
+pub const MAX_BLOCK_SIZE: usize = 4000000;
 
-    Ok(buffer)
+// Upper bounds on sizes read off the wire, checked before anything is
+// allocated for them. The defaults are the consensus limits, so they never
+// reject a valid block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeserializeLimits {
+    // Applies to scripts and to individual witness items.
+    pub max_script_size: usize,
+    pub max_tx_count: usize,
+    pub max_block_size: usize,
 }
 
-pub fn double_hash(data: &[u8]) -> Result<Vec<u8>, io::Error> {
-    Ok(single_hash(single_hash(data)?.as_slice())?)
+impl Default for DeserializeLimits {
+    fn default() -> DeserializeLimits {
+        DeserializeLimits {
+            max_script_size: MAX_BLOCK_SIZE,
+            // Every transaction is at least 60 bytes.
+            max_tx_count: MAX_BLOCK_SIZE / 60,
+            max_block_size: MAX_BLOCK_SIZE,
+        }
+    }
 }
 
-fn concat_and_hash(values: &[Vec<u8>]) -> Result<Vec<u8>, io::Error> {
-    let mut hashes: Vec<Vec<u8>> = Vec::new();
-    for chunk in values.chunks(2) {
-        let mut first = chunk[0].clone();
-        if chunk.len() == 2 {
-            first.extend(chunk[1].iter());
-        } else {
-            first.extend(chunk[0].iter());
+// Reads a length-prefixed byte string. The buffer grows as data arrives,
+// so a bogus length can't allocate more than the input actually holds.
+pub fn read_bytes<R: Read>(reader: &mut R,
+                           what: &'static str,
+                           limit: usize)
+                           -> Result<Vec<u8>, BlockchainError> {
+    let length = VarInt::deserialize_from(reader)?.0;
+    check_limit(what, length, limit)?;
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < length {
+        return Err(BlockchainError::TruncatedInput);
+    }
+
+    Ok(bytes)
+}
+
+pub fn check_limit(what: &'static str, found: u64, limit: usize) -> Result<(), BlockchainError> {
+    if found > limit as u64 {
+        return Err(BlockchainError::LimitExceeded {
+                       what: what,
+                       limit: limit,
+                       found: found,
+                   });
+    }
+
+    Ok(())
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, BlockchainError> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(BlockchainError::InvalidHex);
+    }
+    let mut data: Vec<u8> = Vec::with_capacity(hex.len() / 2);
+    for i in (0..hex.len()).step_by(2) {
+        let byte = hex.get(i..i + 2).ok_or(BlockchainError::InvalidHex)?;
+        data.push(u8::from_str_radix(byte, 16).map_err(|_| BlockchainError::InvalidHex)?);
+    }
+
+    Ok(data)
+}
+
+// Serde helpers for writing byte fields as hex strings.
+#[cfg(feature = "serde")]
+pub mod hex {
+    use crate::prelude::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        super::from_hex(&hex).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub mod hex_list {
+    use crate::prelude::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(items: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(items.iter().map(|item| super::to_hex(item)))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let hexes: Vec<String> = Vec::deserialize(deserializer)?;
+        hexes
+            .iter()
+            .map(|hex| super::from_hex(hex).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+// Computes a merkle root from leaf hashes pushed one at a time. Only one
+// partial root is kept per tree level, so memory is O(log n) in the number
+// of leaves.
+pub struct MerkleStream {
+    levels: Vec<Option<Hash256>>,
+    count: u64,
+}
+
+impl MerkleStream {
+    pub fn new() -> MerkleStream {
+        MerkleStream {
+            levels: Vec::new(),
+            count: 0,
         }
-        hashes.push(double_hash(first.as_slice())?);
     }
 
-    if hashes.len() == 1 {
-        Ok(hashes[0].clone())
-    } else {
-        concat_and_hash(&hashes)
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.push_hash(double_hash(data))
+    }
+
+    pub fn push_hash(&mut self, hash: Hash256) {
+        let mut carry = hash;
+        let mut level = 0;
+        loop {
+            if level == self.levels.len() {
+                self.levels.push(None);
+            }
+            match self.levels[level].take() {
+                Some(left) => {
+                    carry = hash_pair(&left, &carry);
+                    level += 1;
+                }
+                None => {
+                    self.levels[level] = Some(carry);
+                    break;
+                }
+            }
+        }
+        self.count += 1;
+    }
+
+    pub fn finish(mut self) -> Hash256 {
+        if self.count == 0 {
+            return double_hash(&[]);
+        }
+
+        // Walk up from the lowest level. A node without a sibling at its
+        // level is paired with itself, unless it is the last node left.
+        let mut carry: Option<Hash256> = None;
+        for level in 0..self.levels.len() {
+            let above = self.levels[level + 1..].iter().any(|l| l.is_some());
+            let node = match (self.levels[level].take(), carry.take()) {
+                (Some(left), Some(right)) => {
+                    carry = Some(hash_pair(&left, &right));
+                    continue;
+                }
+                (Some(node), None) | (None, Some(node)) => node,
+                (None, None) => continue,
+            };
+            carry = if above {
+                Some(hash_pair(&node, &node))
+            } else {
+                Some(node)
+            };
+        }
+
+        carry.unwrap()
     }
 }
 
-pub fn calculate_merkle(data: &[Vec<u8>]) -> Result<Vec<u8>, io::Error> {
-    if data.is_empty() {
-        return Ok(double_hash(&[])?);
+impl Default for MerkleStream {
+    fn default() -> MerkleStream {
+        MerkleStream::new()
     }
-    let mut hashes: Vec<Vec<u8>> = Vec::new();
+}
+
+fn hash_pair(left: &Hash256, right: &Hash256) -> Hash256 {
+    let mut buffer = [0; 64];
+    buffer[..32].copy_from_slice(left.as_bytes());
+    buffer[32..].copy_from_slice(right.as_bytes());
+    double_hash(&buffer)
+}
+
+// Computes the merkle root implied by a leaf, the sibling hashes on its
+// path to the root, and its index in the tree.
+pub fn merkle_branch_root(leaf: &Hash256, branch: &[Hash256], index: u32) -> Hash256 {
+    let mut hash = *leaf;
+    let mut index = index;
+    for sibling in branch {
+        hash = if index & 1 == 1 {
+            hash_pair(sibling, &hash)
+        } else {
+            hash_pair(&hash, sibling)
+        };
+        index >>= 1;
+    }
+    hash
+}
+
+pub fn calculate_merkle(data: &[Vec<u8>]) -> Hash256 {
+    let mut stream = MerkleStream::new();
     for value in data {
-        hashes.push(double_hash(value.as_slice())?);
+        stream.push(value.as_slice());
     }
-    concat_and_hash(&hashes)
+    stream.finish()
 }
 
 pub struct VarInt(pub u64);
 
 impl Serializable for VarInt {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buffer: Vec<u8> = Vec::new();
+    fn serialize_to<W: Write>(&self, writer: &mut W) -> Result<(), BlockchainError> {
         let value = self.0;
         if value <= 252 {
-            buffer.write_u8(value as u8)?;
-        } else if value <= std::u16::MAX as u64 {
-            buffer.write_u8(0xfd)?;
-            buffer.write_u16::<LittleEndian>(value as u16)?;
-        } else if value <= std::u32::MAX as u64 {
-            buffer.write_u8(0xfe)?;
-            buffer.write_u32::<LittleEndian>(value as u32)?;
+            writer.write_u8(value as u8)?;
+        } else if value <= u16::MAX as u64 {
+            writer.write_u8(0xfd)?;
+            writer.write_u16::<LittleEndian>(value as u16)?;
+        } else if value <= u32::MAX as u64 {
+            writer.write_u8(0xfe)?;
+            writer.write_u32::<LittleEndian>(value as u32)?;
         } else {
-            buffer.write_u8(0xff)?;
-            buffer.write_u64::<LittleEndian>(value)?;
+            writer.write_u8(0xff)?;
+            writer.write_u64::<LittleEndian>(value)?;
         }
 
-        Ok(buffer)
+        Ok(())
     }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, io::Error> {
+    fn serialized_size(&self) -> usize {
+        match self.0 {
+            0..=252 => 1,
+            253..=0xffff => 3,
+            0x10000..=0xffffffff => 5,
+            _ => 9,
+        }
+    }
+
+    // Consensus code only accepts the shortest encoding of each value, so
+    // that every VarInt has exactly one serialization.
+    fn deserialize_from<R: Read>(reader: &mut R) -> Result<Self, BlockchainError> {
+        VarInt::read(reader, true)
+    }
+}
+
+impl VarInt {
+    // Accepts any encoding of the value, including non-minimal ones, for
+    // tolerant parsing of data that doesn't have to match consensus.
+    pub fn deserialize_lenient<R: Read>(reader: &mut R) -> Result<VarInt, BlockchainError> {
+        VarInt::read(reader, false)
+    }
+
+    fn read<R: Read>(reader: &mut R, strict: bool) -> Result<VarInt, BlockchainError> {
         let first_byte = reader.read_u8()?;
-        println!("first byte = {:0x}", first_byte);
-        let value: u64 = match first_byte {
-            0xfd => reader.read_u16::<LittleEndian>()? as u64,
-            0xfe => reader.read_u32::<LittleEndian>()? as u64,
-            0xff => reader.read_u64::<LittleEndian>()?,
-            _ => first_byte as u64,
+        let (value, minimum): (u64, u64) = match first_byte {
+            0xfd => (reader.read_u16::<LittleEndian>()? as u64, 0xfd),
+            0xfe => (reader.read_u32::<LittleEndian>()? as u64, 0x10000),
+            0xff => (reader.read_u64::<LittleEndian>()?, 0x100000000),
+            _ => (first_byte as u64, 0),
         };
+        if strict && value < minimum {
+            return Err(BlockchainError::NonCanonicalVarInt);
+        }
 
         Ok(VarInt(value))
     }
 }
 
+#[cfg(test)]
 mod test {
-    use super::{VarInt, Serializable};
+    use super::{VarInt, Serializable, MerkleStream, calculate_merkle, to_hex, from_hex};
+    use crate::error::BlockchainError;
+    use crate::hash::{Hash256, double_hash};
+
+    #[test]
+    fn test_hex() {
+        assert_eq!("00ff10", to_hex(&[0x00, 0xff, 0x10]));
+        assert_eq!(vec![0x00, 0xff, 0x10], from_hex("00FF10").unwrap());
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("0g").is_err());
+    }
 
     #[test]
     fn test_varint() {
@@ -99,8 +400,82 @@ mod test {
         for item in data {
             let serialized = VarInt(item.0).serialize().unwrap();
             assert_eq!(item.1, serialized);
-            let VarInt(value) = VarInt::deserialize(&mut item.1.as_slice()).unwrap();
+            assert_eq!(serialized.len(), VarInt(item.0).serialized_size());
+            let VarInt(value) = VarInt::deserialize(&item.1).unwrap();
             assert_eq!(item.0, value);
         }
     }
+
+    #[test]
+    fn test_non_canonical_varint() {
+        let data = vec![vec![0xfd, 0x05, 0x00],
+                        vec![0xfe, 0xff, 0xff, 0x00, 0x00],
+                        vec![0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]];
+        for item in data {
+            assert!(matches!(VarInt::deserialize(&item),
+                             Err(BlockchainError::NonCanonicalVarInt)));
+            let mut reader = item.as_slice();
+            VarInt::deserialize_lenient(&mut reader).unwrap();
+            assert!(reader.is_empty());
+        }
+        assert_eq!(0xfd, VarInt::deserialize(&[0xfd, 0xfd, 0x00]).unwrap().0);
+    }
+
+    #[test]
+    fn test_serializable_hex() {
+        assert_eq!("fd0302", VarInt(515).to_hex().unwrap());
+        assert_eq!(515, VarInt::from_hex("FD0302\n").unwrap().0);
+        assert!(VarInt::from_hex("fd03").is_err());
+        assert!(VarInt::from_hex("fd030200").is_err());
+    }
+
+    // A transaction without inputs serializes to bytes that read back as an
+    // extended-format transaction, so the audit leaves it alone.
+    #[cfg(feature = "serialization-audit")]
+    #[test]
+    fn test_serialization_audit() {
+        use crate::locktime::LockTime;
+        use crate::transaction::Transaction;
+
+        assert!(VarInt(515).serialize().is_ok());
+        let transaction = Transaction::new(1, &[], &[], LockTime::ZERO);
+        assert!(!transaction.round_trips());
+        let serialized = transaction.serialize().unwrap();
+        assert!(Transaction::deserialize(&serialized).is_err());
+    }
+
+    fn naive_merkle(data: &[Vec<u8>]) -> Hash256 {
+        let mut level: Vec<Hash256> = data.iter().map(|d| double_hash(d)).collect();
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                let last = level[level.len() - 1];
+                level.push(last);
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                         let mut joined = pair[0].as_bytes().to_vec();
+                         joined.extend(pair[1].as_bytes().iter());
+                         double_hash(joined.as_slice())
+                     })
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn test_merkle_stream() {
+        assert_eq!(double_hash(&[]), calculate_merkle(&[]));
+
+        for count in 1..40 {
+            let data: Vec<Vec<u8>> = (0..count).map(|i| vec![i as u8; 4]).collect();
+            let mut stream = MerkleStream::new();
+            for item in &data {
+                stream.push(item.as_slice());
+            }
+            assert_eq!(count as u64, stream.len());
+            assert_eq!(naive_merkle(&data), stream.finish());
+            assert_eq!(naive_merkle(&data), calculate_merkle(&data));
+        }
+    }
 }